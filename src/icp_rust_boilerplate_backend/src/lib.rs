@@ -2,15 +2,30 @@
 
 #[macro_use]
 extern crate serde;
-use candid::{Decode, Encode};
-use ic_cdk::api::time;
+use candid::{Decode, Encode, Principal};
 use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
 use ic_stable_structures::{BoundedStorable, Cell, DefaultMemoryImpl, StableBTreeMap, Storable};
-use std::{borrow::Cow, cell::RefCell};
+use std::{
+    borrow::Cow,
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+};
 
+//the tests run outside a canister, where the system api isn't available, so they supply
+//their own clock and caller
+#[cfg(not(test))]
+use ic_cdk::api::{caller, time};
+#[cfg(test)]
+use tests::{caller, time};
 
 type Memory = VirtualMemory<DefaultMemoryImpl>;
 type IdCell = Cell<u64, Memory>;
+type OwnerCell = Cell<StorablePrincipal, Memory>;
+type SettingCell = Cell<u64, Memory>;
+type PolicyCell = Cell<FieldPolicy, Memory>;
+type ValueSet = StableBTreeMap<StorableString, (), Memory>;
+type TenantIndex = StableBTreeMap<u64, StorablePrincipal, Memory>;
+type CancelOutcome = (u64, Result<Sales, String>);
 
 #[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
 //we can only have the following timber types:
@@ -41,9 +56,43 @@ struct Timber {
     id: u64,
     timber_type: String,
     timber_size: String,
+    //scaled by 10^quantity_decimals, e.g. 125 for 1.25 at 2 decimals
     quantity: u64,
+    supplier: Option<String>,
+    //the unit the quantity is counted in.
+    //records created before units existed have none and are counted in pieces
+    unit: Option<String>,
+    //where the timber is kept, one of the valid locations
+    location: Option<String>,
+    //what a single unit cost to buy in
+    unit_cost: Option<u64>,
+    //the quantity at or below which the timber counts as low on stock
+    reorder_point: Option<u64>,
+    //an externally assigned stock keeping unit, unique across the timber of every yard
+    sku: Option<String>,
+    //when set and greater than 1, sales must be a multiple of this quantity
+    sale_increment: Option<u64>,
+    //how many pieces make a bundle, used to sell between pieces and bundles
+    bundle_size: Option<u64>,
+    //volume prices sorted by min_quantity, see set_pricing_tiers
+    pricing_tiers: Option<Vec<PricingTier>>,
+    //the standard price of a unit, used for sales that don't give a price
+    default_unit_price: Option<u64>,
+    //the decimal places of quantities, for timber sold by length. quantities of this timber
+    //and its sales are stored scaled. none is 0, whole pieces
+    quantity_decimals: Option<u8>,
+    //the most stock there is room for, none or 0 for no limit
+    max_capacity: Option<u64>,
+    //when set, this batch can't be sold while an older batch of the same type and size in the
+    //yard still has stock, see check_fifo
+    enforce_fifo: Option<bool>,
     created_at: u64,
     updated_at: Option<u64>,
+    //the principal whose yard this record belongs to.
+    //records created before tenants existed have none and belong to the owner
+    tenant: Option<Principal>,
+    //set when the stored bytes of this record could not be decoded
+    decode_error: Option<String>,
 }
 
 //sales struct
@@ -53,20 +102,56 @@ struct Sales {
     timber_id: u64,
     quantity: u64,
     price: u64,
+    customer: Option<String>,
     created_at: u64,
     updated_at: Option<u64>,
+    //the principal whose yard this record belongs to.
+    //records created before tenants existed have none and belong to the owner
+    tenant: Option<Principal>,
+    //set on the entry reverse_sale creates, whose quantity and revenue count negatively
+    is_reversal: Option<bool>,
+    //the sales a reversal entry reverses
+    reversal_of: Option<u64>,
+    //the order this sales belongs to, see do_insert_order
+    order_id: Option<u64>,
+    //set when the stored bytes of this record could not be decoded
+    decode_error: Option<String>,
+}
+
+//order struct to group the sales made in a single transaction
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct Order {
+    id: u64,
+    customer: String,
+    sale_ids: Vec<u64>,
+    created_at: u64,
+    //the principal whose yard this record belongs to.
+    //records created before tenants existed have none and belong to the owner
+    tenant: Option<Principal>,
+    //set when the stored bytes of this record could not be decoded
+    decode_error: Option<String>,
 }
 
 // a trait that must be implemented for a struct that is stored in a stable struct
 impl Storable for Timber {
     //converts the struct to bytes
-    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
-        // attempt to serialize the struct using the Encode! macro
-        Cow::Owned(Encode!(self).unwrap())
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        // attempt to serialize the struct using the Encode! macro, trapping with context if it fails
+        Cow::Owned(Encode!(self).unwrap_or_else(|e| {
+            ic_cdk::trap(&format!("cannot encode a timber with id={}: {}", self.id, e))
+        }))
     }
 
+    //a record that can't be decoded comes back as a default one with decode_error set,
+    //so a single corrupt record doesn't trap every query iterating the map
     fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
-        Decode!(bytes.as_ref(), Self).unwrap()
+        Decode!(bytes.as_ref(), Self).unwrap_or_else(|e| {
+            ic_cdk::println!("cannot decode a timber record: {}", e);
+            Self {
+                decode_error: Some(e.to_string()),
+                ..Default::default()
+            }
+        })
     }
 }
 
@@ -78,12 +163,20 @@ impl BoundedStorable for Timber {
 
 // a trait that must be implemented for a struct that is stored in a stable struct
 impl Storable for Sales {
-    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
-        Cow::Owned(Encode!(self).unwrap())
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap_or_else(|e| {
+            ic_cdk::trap(&format!("cannot encode a sales with id={}: {}", self.id, e))
+        }))
     }
 
     fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
-        Decode!(bytes.as_ref(), Self).unwrap()
+        Decode!(bytes.as_ref(), Self).unwrap_or_else(|e| {
+            ic_cdk::println!("cannot decode a sales record: {}", e);
+            Self {
+                decode_error: Some(e.to_string()),
+                ..Default::default()
+            }
+        })
     }
 }
 
@@ -93,6 +186,480 @@ impl BoundedStorable for Sales {
     const IS_FIXED_SIZE: bool = false;
 }
 
+// a trait that must be implemented for a struct that is stored in a stable struct
+impl Storable for Order {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap_or_else(|e| {
+            ic_cdk::trap(&format!("cannot encode a order with id={}: {}", self.id, e))
+        }))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap_or_else(|e| {
+            ic_cdk::println!("cannot decode a order record: {}", e);
+            Self {
+                decode_error: Some(e.to_string()),
+                ..Default::default()
+            }
+        })
+    }
+}
+
+// another trait that must be implemented for a struct that is stored in a stable struct
+// an order holds a list of sale ids, so it gets more room than the other records
+impl BoundedStorable for Order {
+    const MAX_SIZE: u32 = 4096;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+//how divisions in price calculations are rounded
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Debug)]
+enum RoundingMode {
+    //towards zero, what plain integer division does
+    #[default]
+    Floor,
+    Ceil,
+    //to the nearest value, with .5 rounded up
+    HalfUp,
+}
+
+impl RoundingMode {
+    //converts the mode to the value kept in its setting cell
+    fn to_setting(self) -> u64 {
+        match self {
+            RoundingMode::Floor => 0,
+            RoundingMode::Ceil => 1,
+            RoundingMode::HalfUp => 2,
+        }
+    }
+
+    //converts a setting cell value back to the mode, unknown values read as floor
+    fn from_setting(value: u64) -> Self {
+        match value {
+            1 => RoundingMode::Ceil,
+            2 => RoundingMode::HalfUp,
+            _ => RoundingMode::Floor,
+        }
+    }
+}
+
+//the class abc_classification puts a timber in, A being the most valuable stock
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Debug)]
+enum AbcClass {
+    A,
+    B,
+    C,
+}
+
+//how much the update methods log to the replica log
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Debug)]
+enum LogLevel {
+    Off,
+    //only calls that return an error
+    Error,
+    //every call and every error
+    #[default]
+    Info,
+}
+
+impl LogLevel {
+    //converts the level to the value kept in its setting cell
+    fn to_setting(self) -> u64 {
+        match self {
+            LogLevel::Off => 0,
+            LogLevel::Error => 1,
+            LogLevel::Info => 2,
+        }
+    }
+
+    //converts a setting cell value back to the level, unknown values read as info
+    fn from_setting(value: u64) -> Self {
+        match value {
+            0 => LogLevel::Off,
+            1 => LogLevel::Error,
+            _ => LogLevel::Info,
+        }
+    }
+}
+
+//a record of a sales price being changed
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct PriceChange {
+    id: u64,
+    sales_id: u64,
+    old_price: u64,
+    new_price: u64,
+    changed_at: u64,
+}
+
+// a trait that must be implemented for a struct that is stored in a stable struct
+impl Storable for PriceChange {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap_or_else(|e| {
+            ic_cdk::trap(&format!(
+                "cannot encode a price change with id={}: {}",
+                self.id, e
+            ))
+        }))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap_or_else(|e| {
+            ic_cdk::println!("cannot decode a price change record: {}", e);
+            Self::default()
+        })
+    }
+}
+
+// another trait that must be implemented for a struct that is stored in a stable struct
+impl BoundedStorable for PriceChange {
+    const MAX_SIZE: u32 = 256;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+//a record of the default unit price of a timber being changed
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct DefaultPriceChange {
+    id: u64,
+    timber_id: u64,
+    old_price: u64,
+    new_price: u64,
+    changed_at: u64,
+}
+
+// a trait that must be implemented for a struct that is stored in a stable struct
+impl Storable for DefaultPriceChange {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap_or_else(|e| {
+            ic_cdk::trap(&format!(
+                "cannot encode a default price change with id={}: {}",
+                self.id, e
+            ))
+        }))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap_or_else(|e| {
+            ic_cdk::println!("cannot decode a default price change record: {}", e);
+            Self::default()
+        })
+    }
+}
+
+// another trait that must be implemented for a struct that is stored in a stable struct
+impl BoundedStorable for DefaultPriceChange {
+    const MAX_SIZE: u32 = 256;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+//a stock adjustment waiting for a second authorized principal to approve it
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct PendingAdjustment {
+    id: u64,
+    timber_id: u64,
+    //positive when stock comes in, negative when it goes out
+    delta: i64,
+    reason: String,
+    requested_by: Principal,
+    requested_at: u64,
+    //the yard the timber was in when the adjustment was asked for. adjustments asked for
+    //before this was kept have none and are in the requester's yard
+    yard: Option<Principal>,
+}
+
+//principal has no default, so the fallback for a record that can't be decoded is spelled out
+impl Default for PendingAdjustment {
+    fn default() -> Self {
+        Self {
+            id: 0,
+            timber_id: 0,
+            delta: 0,
+            reason: String::new(),
+            requested_by: Principal::anonymous(),
+            requested_at: 0,
+            yard: None,
+        }
+    }
+}
+
+// a trait that must be implemented for a struct that is stored in a stable struct
+impl Storable for PendingAdjustment {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap_or_else(|e| {
+            ic_cdk::trap(&format!(
+                "cannot encode a pending adjustment with id={}: {}",
+                self.id, e
+            ))
+        }))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap_or_else(|e| {
+            ic_cdk::println!("cannot decode a pending adjustment record: {}", e);
+            Self::default()
+        })
+    }
+}
+
+// another trait that must be implemented for a struct that is stored in a stable struct
+impl BoundedStorable for PendingAdjustment {
+    //the reason is at most MAX_REASON_LENGTH bytes
+    const MAX_SIZE: u32 = 512;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+//a record of a change to the stock of a timber, used to reconstruct past quantities
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct StockMovement {
+    timber_id: u64,
+    //positive when stock came in, negative when it went out
+    delta: i64,
+    reason: String,
+    created_at: u64,
+}
+
+// a trait that must be implemented for a struct that is stored in a stable struct
+impl Storable for StockMovement {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap_or_else(|e| {
+            ic_cdk::trap(&format!(
+                "cannot encode a stock movement of timber with id={}: {}",
+                self.timber_id, e
+            ))
+        }))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap_or_else(|e| {
+            ic_cdk::println!("cannot decode a stock movement record: {}", e);
+            Self::default()
+        })
+    }
+}
+
+// another trait that must be implemented for a struct that is stored in a stable struct
+impl BoundedStorable for StockMovement {
+    const MAX_SIZE: u32 = 512;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+//the unit price a timber sells at from min_quantity upwards
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Debug)]
+struct PricingTier {
+    min_quantity: u64,
+    unit_price: u64,
+}
+
+//which of the optional fields must be given, set by the owner per deployment
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, Default, Debug)]
+struct FieldPolicy {
+    //timber must have a supplier
+    require_supplier: bool,
+    //sales must have a customer
+    require_customer: bool,
+    //timber must have a unit cost
+    require_cost: bool,
+    //update_timber must give a reason when it changes the quantity.
+    //policies stored before this existed have none, which is off
+    require_reason: Option<bool>,
+}
+
+// a trait that must be implemented for a struct that is stored in a stable struct
+impl Storable for FieldPolicy {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(
+            Encode!(self).unwrap_or_else(|e| {
+                ic_cdk::trap(&format!("cannot encode the field policy: {}", e))
+            }),
+        )
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap_or_else(|e| {
+            ic_cdk::println!("cannot decode the field policy: {}", e);
+            Self::default()
+        })
+    }
+}
+
+//a hold on some stock of a timber until it expires or is released
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct Reservation {
+    id: u64,
+    timber_id: u64,
+    quantity: u64,
+    created_at: u64,
+    expires_at: u64,
+    //the principal holding the stock, who can sell it and release the hold.
+    //reservations made before holders were recorded have none and belong to the owner
+    holder: Option<Principal>,
+}
+
+// a trait that must be implemented for a struct that is stored in a stable struct
+impl Storable for Reservation {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap_or_else(|e| {
+            ic_cdk::trap(&format!(
+                "cannot encode a reservation with id={}: {}",
+                self.id, e
+            ))
+        }))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap_or_else(|e| {
+            ic_cdk::println!("cannot decode a reservation record: {}", e);
+            Self::default()
+        })
+    }
+}
+
+// another trait that must be implemented for a struct that is stored in a stable struct
+impl BoundedStorable for Reservation {
+    const MAX_SIZE: u32 = 256;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+//a wrapper so a principal can be stored in a stable struct
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct StorablePrincipal(Principal);
+
+//stable struct tuple keys need a default, the anonymous principal stands in for none
+impl Default for StorablePrincipal {
+    fn default() -> Self {
+        Self(Principal::anonymous())
+    }
+}
+
+// a trait that must be implemented for a struct that is stored in a stable struct
+impl Storable for StorablePrincipal {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Borrowed(self.0.as_slice())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Self(Principal::from_slice(bytes.as_ref()))
+    }
+}
+
+// another trait that must be implemented for a struct that is stored in a stable struct
+impl BoundedStorable for StorablePrincipal {
+    const MAX_SIZE: u32 = 29;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+//the times of a caller's recent update calls, used for rate limiting
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct CallLog {
+    timestamps: Vec<u64>,
+}
+
+// a trait that must be implemented for a struct that is stored in a stable struct
+impl Storable for CallLog {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap_or_else(|e| {
+            ic_cdk::trap(&format!("cannot encode a call log: {}", e))
+        }))
+    }
+
+    //an unreadable call log just starts over
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap_or_else(|e| {
+            ic_cdk::println!("cannot decode a call log: {}", e);
+            Self::default()
+        })
+    }
+}
+
+// another trait that must be implemented for a struct that is stored in a stable struct
+impl BoundedStorable for CallLog {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+//a wrapper so a short string can be used as a key in a stable struct
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Default)]
+struct StorableString(String);
+
+// a trait that must be implemented for a struct that is stored in a stable struct
+impl Storable for StorableString {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Borrowed(self.0.as_bytes())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Self(String::from_utf8_lossy(bytes.as_ref()).into_owned())
+    }
+}
+
+// another trait that must be implemented for a struct that is stored in a stable struct
+impl BoundedStorable for StorableString {
+    const MAX_SIZE: u32 = 64;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+//the maximum number of items a single order can hold, keeps an order within Order::MAX_SIZE
+const MAX_ORDER_ITEMS: usize = 100;
+
+//the timber types listed on the Timber struct
+const VALID_TIMBER_TYPES: [&str; 5] = ["cyprus", "pine", "oak", "cedar", "spruce"];
+
+//the timber sizes listed on the Timber struct
+const VALID_TIMBER_SIZES: [&str; 15] = [
+    "2x4", "2x6", "2x8", "2x10", "3x2", "3x4", "4x2", "4x4", "4x6", "6x2", "6x4", "8x2", "8x4",
+    "10x2", "10x4",
+];
+
+//the units the valid units start out with
+const DEFAULT_UNITS: [&str; 3] = ["piece", "bundle", "pallet"];
+
+//the unit a timber is counted in when none is given
+const DEFAULT_UNIT: &str = "piece";
+
+//the number of nanoseconds in a day
+const DAY_NS: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+//the longest search term accepted by the search queries
+const MAX_SEARCH_LENGTH: usize = 32;
+
+//the token delete_all_timber must be called with, awkward on purpose
+const DELETE_ALL_TIMBER_CONFIRMATION: &str = "DELETE ALL TIMBER";
+
+//the window the rate limit counts calls in
+const RATE_LIMIT_WINDOW_NS: u64 = 60 * 1_000_000_000;
+
+//the highest rate limit that can be set, keeps a CallLog within CallLog::MAX_SIZE
+const MAX_RATE_LIMIT: u64 = 100;
+
+//the number of callers tracked by the rate limit before idle callers are swept out
+const MAX_TRACKED_CALLERS: u64 = 10_000;
+
+//the maximum number of records a paged query returns
+const MAX_PAGE_SIZE: u64 = 100;
+
+//the longest a reservation can hold stock for
+const MAX_RESERVATION_TTL_NS: u64 = 7 * DAY_NS;
+
+//the maximum number of pricing tiers a timber can have, bounded so the record stays
+//within its storage size
+const MAX_PRICING_TIERS: usize = 5;
+
+//the longest reason update_timber can record with a quantity change, keeps the stock
+//movement within StockMovement::MAX_SIZE
+const MAX_REASON_LENGTH: usize = 200;
+
+//the most decimal places a timber quantity can have
+const MAX_QUANTITY_DECIMALS: u8 = 3;
+
+//the number of latest sales timber_detail returns
+const RECENT_SALES_LIMIT: usize = 5;
+
+//the window of sales restock_suggestions measures sales velocity over
+const RESTOCK_VELOCITY_WINDOW_NS: u64 = 30 * DAY_NS;
+
+//how far in the past the cutoff of purge_cancelled_sales_before must be at least
+const MIN_PURGE_AGE_NS: u64 = 90 * DAY_NS;
+
 //thread local storage for the memory manager
 thread_local! {
     static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> = RefCell::new(
@@ -113,188 +680,5001 @@ thread_local! {
         RefCell::new(StableBTreeMap::init(
             MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(2)))
     ));
-}
 
-//a struct to hold the payload for the timber
-#[derive(candid::CandidType, Serialize, Deserialize, Default)]
-struct TimberPayload {
-    timber_type: String,
-    timber_size: String,
-    quantity: u64,
+    static ORDER_STORAGE: RefCell<StableBTreeMap<u64, Order, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(3)))
+    ));
+
+    //deleted sales are kept here so they can be restored
+    static DELETED_SALES: RefCell<StableBTreeMap<u64, Sales, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(5)))
+    ));
+
+    //the units a timber can be counted in, seeded with DEFAULT_UNITS
+    static VALID_UNITS: RefCell<ValueSet> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(10)))
+    ));
+
+    //1 once the valid units have been seeded, see seed_default_units
+    static UNITS_SEEDED: RefCell<SettingCell> = RefCell::new(
+        SettingCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(36))), 0)
+            .expect("Cannot create the units seeded cell")
+    );
+
+    //the locations a timber can be kept in
+    static VALID_LOCATIONS: RefCell<ValueSet> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(11)))
+    ));
+
+    //the maximum number of update calls a caller can make per minute, 0 means unlimited
+    static RATE_LIMIT: RefCell<SettingCell> = RefCell::new(
+        SettingCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(12))), 60)
+            .expect("Cannot create the rate limit cell")
+    );
+
+    //the sku index from before skus were kept per yard, moved into SKU_INDEX on upgrade
+    static LEGACY_SKU_INDEX: RefCell<StableBTreeMap<StorableString, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(14)))
+    ));
+
+    //maps a yard and a sku to the id of the timber in that yard that has it, see sku_key.
+    //yards pick their skus independently, so the same sku can be in several yards
+    static SKU_INDEX: RefCell<StableBTreeMap<(StorablePrincipal, StorableString), u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(35)))
+    ));
+
+    //the RoundingMode used in price calculations, see RoundingMode::to_setting
+    static ROUNDING_MODE: RefCell<SettingCell> = RefCell::new(
+        SettingCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(15))), 0)
+            .expect("Cannot create the rounding mode cell")
+    );
+
+    static PRICE_HISTORY: RefCell<StableBTreeMap<u64, PriceChange, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(16)))
+    ));
+
+    //the lowest and highest price a sale can have, 0 means no limit
+    static MIN_PRICE: RefCell<SettingCell> = RefCell::new(
+        SettingCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(17))), 0)
+            .expect("Cannot create the min price cell")
+    );
+
+    static MAX_PRICE: RefCell<SettingCell> = RefCell::new(
+        SettingCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(18))), 0)
+            .expect("Cannot create the max price cell")
+    );
+
+    //cumulative share of stock value, in percent, that abc_classification puts in class A
+    static ABC_A_PERCENT: RefCell<SettingCell> = RefCell::new(
+        SettingCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(20))), 70)
+            .expect("Cannot create the abc a percent cell")
+    );
+
+    //cumulative share of stock value, in percent, that abc_classification puts in class A or B
+    static ABC_B_PERCENT: RefCell<SettingCell> = RefCell::new(
+        SettingCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(21))), 90)
+            .expect("Cannot create the abc b percent cell")
+    );
+
+    static LOG_LEVEL: RefCell<SettingCell> = RefCell::new(
+        SettingCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(22))),
+            LogLevel::Info.to_setting(),
+        )
+        .expect("Cannot create the log level cell")
+    );
+
+    static RESERVATIONS: RefCell<StableBTreeMap<u64, Reservation, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(23)))
+    ));
+
+    //1 when sales below the unit cost of their timber are rejected
+    static BLOCK_BELOW_COST: RefCell<SettingCell> = RefCell::new(
+        SettingCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(24))), 0)
+            .expect("Cannot create the block below cost cell")
+    );
+
+    static AUTHORIZED_PRINCIPALS: RefCell<StableBTreeMap<StorablePrincipal, (), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(25)))
+    ));
+
+    static FIELD_POLICY: RefCell<PolicyCell> = RefCell::new(
+        PolicyCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(26))),
+            FieldPolicy::default(),
+        )
+        .expect("Cannot create the field policy cell")
+    );
+
+    //the canister told about low stock, the anonymous principal means none
+    static NOTIFY_CANISTER: RefCell<OwnerCell> = RefCell::new(
+        OwnerCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(27))),
+            StorablePrincipal(Principal::anonymous()),
+        )
+        .expect("Cannot create the notify canister cell")
+    );
+
+    //the days a supplier takes to deliver, used by restock_suggestions
+    static LEAD_TIME_DAYS: RefCell<SettingCell> = RefCell::new(
+        SettingCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(28))), 7)
+            .expect("Cannot create the lead time days cell")
+    );
+
+    //how many times each update method has been called, counted in logged
+    static METHOD_CALLS: RefCell<StableBTreeMap<StorableString, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(29)))
+    ));
+
+    static DEFAULT_PRICE_HISTORY: RefCell<StableBTreeMap<u64, DefaultPriceChange, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(30)))
+    ));
+
+    //maps a sale id to the id of the order it belongs to
+    static SALE_ORDERS: RefCell<StableBTreeMap<u64, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(32)))
+    ));
+
+    //stock adjustments waiting for approval, keyed by id
+    static PENDING_ADJUSTMENTS: RefCell<StableBTreeMap<u64, PendingAdjustment, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(33)))
+    ));
+
+    //the tenant of every timber and sales by id, so which yard a record is in can be told
+    //without decoding the whole record. the anonymous principal stands for no tenant
+    static TIMBER_TENANTS: RefCell<TenantIndex> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(37)))
+    ));
+
+    static SALES_TENANTS: RefCell<TenantIndex> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(38)))
+    ));
+
+    //maps the id of a reversed sale to the id of its reversal
+    static REVERSED_SALES: RefCell<StableBTreeMap<u64, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(34)))
+    ));
+
+    //how long after a sale anyone may delete it, after that only the owner can. 0 means no limit
+    static DELETE_GRACE_NS: RefCell<SettingCell> = RefCell::new(
+        SettingCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(31))), 0)
+            .expect("Cannot create the delete grace cell")
+    );
+
+    //stock movements keyed by (timber id, movement number within that timber)
+    static STOCK_MOVEMENTS: RefCell<StableBTreeMap<(u64, u64), StockMovement, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(19)))
+    ));
+
+    static CALL_LOGS: RefCell<StableBTreeMap<StorablePrincipal, CallLog, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(13)))
+    ));
+
+    //who may call what:
+    //- the owner changes settings and runs the owner only maintenance endpoints
+    //- authorized principals are staff the owner trusts to run the register, see
+    //  add_authorized_principal. they work in the owner's yard (see yard)
+    //- anyone else can manage timber and sales, but only in their own yard (see in_partition)
+    //the anonymous principal means no owner has been set yet
+    static OWNER: RefCell<OwnerCell> = RefCell::new(
+        OwnerCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(4))),
+            StorablePrincipal(Principal::anonymous()),
+        )
+        .expect("Cannot create the owner cell")
+    );
+
+    //0 means there is no limit on the number of records
+    static MAX_TIMBER_RECORDS: RefCell<SettingCell> = RefCell::new(
+        SettingCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(6))), 0)
+            .expect("Cannot create the max timber records cell")
+    );
+
+    static MAX_SALES_RECORDS: RefCell<SettingCell> = RefCell::new(
+        SettingCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(7))), 0)
+            .expect("Cannot create the max sales records cell")
+    );
+
+    //the business hours window as nanoseconds into the utc day, equal values mean always open
+    static BUSINESS_HOURS_START_NS: RefCell<SettingCell> = RefCell::new(
+        SettingCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(8))), 0)
+            .expect("Cannot create the business hours start cell")
+    );
+
+    static BUSINESS_HOURS_END_NS: RefCell<SettingCell> = RefCell::new(
+        SettingCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(9))), 0)
+            .expect("Cannot create the business hours end cell")
+    );
+}
+
+//a struct to hold the payload for the timber
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+struct TimberPayload {
+    timber_type: String,
+    timber_size: String,
+    quantity: u64,
+    supplier: Option<String>,
+    //defaults to piece
+    unit: Option<String>,
+    location: Option<String>,
+    unit_cost: Option<u64>,
+    reorder_point: Option<u64>,
+    sku: Option<String>,
+    sale_increment: Option<u64>,
+    bundle_size: Option<u64>,
+    default_unit_price: Option<u64>,
+    //fixed once the timber is added, since stored quantities depend on it
+    quantity_decimals: Option<u8>,
+    max_capacity: Option<u64>,
+    enforce_fifo: Option<bool>,
+}
+
+//a struct to hold the payload for the sales
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct SalesPayload {
+    timber_id: u64,
+    quantity: u64,
+    //defaults to the tier price or else the default unit price of the timber
+    price: Option<u64>,
+    //when the sale actually happened, for sales entered late. owner only
+    occurred_at: Option<u64>,
+    //sales created through an order get the order's customer
+    customer: Option<String>,
+    //the unit the quantity is given in, defaults to the unit of the timber
+    unit: Option<SaleUnit>,
+    //allows selling pieces of bundle timber that don't make whole bundles
+    split_bundles: Option<bool>,
+}
+
+//the unit the quantity of a sale is given in, converted with the timber's bundle size
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Debug)]
+enum SaleUnit {
+    Piece,
+    Bundle,
+}
+
+impl SalesPayload {
+    //the price of a unit once resolve_price has filled it in
+    fn unit_price(&self) -> u64 {
+        self.price.unwrap_or(0)
+    }
+}
+
+impl SaleUnit {
+    //the name of the matching timber unit
+    fn as_unit(self) -> &'static str {
+        match self {
+            SaleUnit::Piece => "piece",
+            SaleUnit::Bundle => "bundle",
+        }
+    }
+}
+
+//a struct to hold the payload for the sales
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+struct SalesUpdatePayload {
+    id: u64,
+    quantity: u64,
+    price: u64,
+}
+
+//a struct to hold the payload for the timber
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+struct TimberUpdatePayload {
+    id: u64,
+    timber_type: String,
+    timber_size: String,
+    quantity: u64,
+    supplier: Option<String>,
+    //keeps the current unit when not given
+    unit: Option<String>,
+    location: Option<String>,
+    unit_cost: Option<u64>,
+    reorder_point: Option<u64>,
+    sale_increment: Option<u64>,
+    bundle_size: Option<u64>,
+    default_unit_price: Option<u64>,
+    max_capacity: Option<u64>,
+    enforce_fifo: Option<bool>,
+    //why the quantity changed, kept with the stock movement
+    reason: Option<String>,
+}
+
+//a struct to hold the record counts and their limits, 0 means unlimited
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+struct RecordUsage {
+    timber_count: u64,
+    max_timber_records: u64,
+    sales_count: u64,
+    max_sales_records: u64,
+}
+
+//a struct to hold the sales figures of a timber
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+struct SalesSummary {
+    transactions: u64,
+    units_sold: u64,
+    revenue: u64,
+    //revenue / units_sold, rounded with the configured rounding mode
+    average_price: u64,
+}
+
+//a struct to hold the stock figures of a timber type
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+struct TypeReport {
+    timber_type: String,
+    total_quantity: u64,
+    //quantity * unit_cost over the records that have a unit cost
+    total_value: u64,
+    record_count: u64,
+    //records at or below their reorder point
+    low_stock_count: u64,
+}
+
+//a struct to hold an order together with its sales
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+struct OrderDetails {
+    order: Order,
+    sales: Vec<Sales>,
+    total: u64,
+}
+
+//a struct to hold a timber together with its available stock and latest sales
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+struct TimberDetail {
+    timber: Timber,
+    //the quantity not held by reservations
+    available: u64,
+    //the quantity held by reservations that haven't expired
+    active_reservations: u64,
+    //newest first
+    recent_sales: Vec<Sales>,
+}
+
+//a struct to hold the timber, sales and orders written out by export_snapshot
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+struct Snapshot {
+    timber: Vec<Timber>,
+    sales: Vec<Sales>,
+    //snapshots taken before orders were exported have none
+    orders: Option<Vec<Order>>,
+}
+
+//a struct to hold the state reported to uptime monitors
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+struct HealthStatus {
+    //false when some stored records can't be decoded
+    ok: bool,
+    timber_count: u64,
+    sales_count: u64,
+    id_counter: u64,
+    time: u64,
+}
+
+//a struct to hold the timber and sales changed since a point in time
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+struct ChangeSet {
+    timber: Vec<Timber>,
+    sales: Vec<Sales>,
+    //the time of this query, to pass as timestamp_ns in the next changes_since call
+    server_time: u64,
+}
+
+//a struct to hold every owner settable setting, see the matching set_ methods
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct Config {
+    rate_limit: u64,
+    rounding_mode: RoundingMode,
+    min_price: u64,
+    max_price: u64,
+    block_below_cost: bool,
+    abc_a_percent: u64,
+    abc_b_percent: u64,
+    log_level: LogLevel,
+    field_policy: FieldPolicy,
+    notify_canister: Option<Principal>,
+    lead_time_days: u64,
+    delete_grace_ns: u64,
+    max_timber_records: u64,
+    max_sales_records: u64,
+    business_hours_start_ns: u64,
+    business_hours_end_ns: u64,
+}
+
+//a struct to hold a sales as written by export_sales_json, with the type and size of its
+//timber, none if the timber no longer exists
+#[derive(Serialize)]
+struct SalesExport {
+    id: u64,
+    timber_id: u64,
+    timber_type: Option<String>,
+    timber_size: Option<String>,
+    quantity: u64,
+    price: u64,
+    customer: Option<String>,
+    created_at: u64,
+    updated_at: Option<u64>,
+    is_reversal: bool,
+    reversal_of: Option<u64>,
+    order_id: Option<u64>,
+}
+
+//a struct to hold what global_search found, each at most MAX_PAGE_SIZE records in id order
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+struct SearchResults {
+    timber: Vec<Timber>,
+    sales: Vec<Sales>,
+}
+
+//a struct to hold the ids of the in stock timber by how long ago it arrived
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+struct AgingBuckets {
+    under_30_days: Vec<u64>,
+    //30 to 90 days inclusive
+    from_30_to_90_days: Vec<u64>,
+    over_90_days: Vec<u64>,
+}
+
+//a struct to hold how much of a low stock timber to order
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct RestockSuggestion {
+    timber_id: u64,
+    current_quantity: u64,
+    reorder_point: u64,
+    //daily sales velocity * lead time days, rounded up, - current quantity, at least 0
+    suggested_order_quantity: u64,
+}
+
+//the principal installing the canister becomes its owner
+#[ic_cdk::init]
+fn init() {
+    set_owner(caller());
+    seed_default_units();
+}
+
+//canisters installed before ownership existed get the upgrading controller as owner
+#[ic_cdk::post_upgrade]
+fn post_upgrade() {
+    if owner() == Principal::anonymous() {
+        set_owner(caller());
+    }
+    seed_default_units();
+    index_order_sales();
+    backfill_sale_orders();
+    index_reversals();
+    index_skus_by_yard();
+    index_tenants();
+}
+
+//function to get a timber by id
+#[ic_cdk::query]
+fn get_timber(id: u64) -> Result<Timber, String> {
+    match _get_timber(&id) {
+        Some(Timber {
+            decode_error: Some(e),
+            ..
+        }) => Err(format!("a timber with id={} is corrupted: {}", id, e)),
+        Some(timber) => Ok(timber),
+        None => Err(format!("a timber with id={} not found", id)),
+    }
+}
+
+
+//function to get a sales by id
+#[ic_cdk::query]
+fn get_sales(id: u64) -> Result<Sales, String> {
+    match _get_sales(&id) {
+        Some(Sales {
+            decode_error: Some(e),
+            ..
+        }) => Err(format!("a sales with id={} is corrupted: {}", id, e)),
+        Some(sales) => Ok(sales),
+        None => Err(format!("a sales with id={} not found", id)),
+    }
+}
+
+//function to add a timber
+#[ic_cdk::update]
+fn add_timber(timber: TimberPayload) -> Result<Timber, String> {
+    logged(
+        "add_timber",
+        format!(
+            "timber_type={} timber_size={} quantity={}",
+            timber.timber_type, timber.timber_size, timber.quantity
+        ),
+        || {
+            check_rate_limit()?;
+            let timber = check_timber_payload(timber)?;
+            let id = generate_unique_id()?;
+            let timber = Timber {
+                id,
+                timber_type: timber.timber_type,
+                timber_size: timber.timber_size,
+                quantity: timber.quantity,
+                supplier: timber.supplier,
+                unit: timber.unit,
+                location: timber.location,
+                unit_cost: timber.unit_cost,
+                reorder_point: timber.reorder_point,
+                sku: timber.sku,
+                sale_increment: timber.sale_increment,
+                bundle_size: timber.bundle_size,
+                pricing_tiers: None,
+                default_unit_price: timber.default_unit_price,
+                quantity_decimals: timber.quantity_decimals,
+                max_capacity: timber.max_capacity,
+                enforce_fifo: timber.enforce_fifo,
+                created_at: time(),
+                updated_at: None,
+                tenant: Some(yard()),
+                decode_error: None,
+            };
+            do_insert_timber(&timber)?;
+            if let Some(sku) = &timber.sku {
+                SKU_INDEX.with(|index| index.borrow_mut().insert(sku_key(yard(), sku), id));
+            }
+            do_record_movement(id, to_delta(timber.quantity), "intake");
+            Ok(timber)
+        },
+    )
+}
+
+//function to add a sales. the sold quantity is deducted from the timber stock
+#[ic_cdk::update]
+fn add_sales(sales: SalesPayload) -> Result<Sales, String> {
+    logged(
+        "add_sales",
+        format!("timber_id={} quantity={}", sales.timber_id, sales.quantity),
+        || {
+            check_rate_limit()?;
+            do_add_sales(sales)
+        },
+    )
+}
+
+//function to sell stock in one step, for walk-in sales that need no hold. the quantity must
+//be available after other principals' active reservations, and every check runs before the
+//stock is deducted and the sale created, so either the whole sale is made or nothing changes
+#[ic_cdk::update]
+fn reserve_and_sell(
+    timber_id: u64,
+    quantity: u64,
+    price: Option<u64>,
+    customer: Option<String>,
+) -> Result<Sales, String> {
+    logged(
+        "reserve_and_sell",
+        format!("timber_id={} quantity={}", timber_id, quantity),
+        || {
+            check_rate_limit()?;
+            do_add_sales(SalesPayload {
+                timber_id,
+                quantity,
+                price,
+                customer,
+                ..Default::default()
+            })
+        },
+    )
+}
+
+//function to create an order. every item is checked for stock before any sale is created,
+//so the whole order is rejected if a single item fails
+#[ic_cdk::update]
+fn create_order(customer: String, items: Vec<SalesPayload>) -> Result<Order, String> {
+    logged("create_order", format!("items={}", items.len()), || {
+        check_rate_limit()?;
+        if customer.trim().is_empty() {
+            return Err("Customer must not be empty".to_string());
+        }
+        if items.is_empty() {
+            return Err("an order must have at least one item".to_string());
+        }
+        if items.len() > MAX_ORDER_ITEMS {
+            return Err(format!(
+                "an order can have at most {} items",
+                MAX_ORDER_ITEMS
+            ));
+        }
+        let items = items
+            .into_iter()
+            .map(|item| convert_sale_unit(item).and_then(resolve_price))
+            .collect::<Result<Vec<SalesPayload>, String>>()?;
+        check_record_limit(
+            sales_count(),
+            items.len() as u64,
+            get_setting(&MAX_SALES_RECORDS),
+        )?;
+        check_business_hours()?;
+        check_occurred_at(&items)?;
+        for item in &items {
+            check_price(item.unit_price())?;
+            check_line_total(item.quantity, item.unit_price())?;
+        }
+        check_sale_increment(&items)?;
+        check_below_cost(&items)?;
+        check_stock(&items)?;
+        check_fifo(&items)?;
+
+        //all ids are taken up front so a counter failure can't leave a half created order
+        let order_id = generate_unique_id()?;
+        let ids = items
+            .iter()
+            .map(|_| generate_unique_id())
+            .collect::<Result<Vec<u64>, String>>()?;
+        let sale_ids = ids
+            .into_iter()
+            .zip(items)
+            .map(|(id, item)| {
+                let item = SalesPayload {
+                    customer: Some(customer.clone()),
+                    ..item
+                };
+                do_create_sales(id, item).id
+            })
+            .collect();
+        let order = Order {
+            id: order_id,
+            customer,
+            sale_ids,
+            created_at: time(),
+            tenant: Some(yard()),
+            decode_error: None,
+        };
+        do_insert_order(&order);
+        Ok(order)
+    })
+}
+
+//function to group sales that were created one by one into an order after the fact. every
+//sale must exist, belong to customer or have no customer, and not be in another order.
+//sales with no customer are given customer
+#[ic_cdk::update]
+fn group_sales_into_order(sale_ids: Vec<u64>, customer: String) -> Result<Order, String> {
+    logged(
+        "group_sales_into_order",
+        format!("sales={} customer={}", sale_ids.len(), customer),
+        || {
+            check_rate_limit()?;
+            if customer.trim().is_empty() {
+                return Err("Customer must not be empty".to_string());
+            }
+            if sale_ids.is_empty() {
+                return Err("an order must have at least one item".to_string());
+            }
+            if sale_ids.len() > MAX_ORDER_ITEMS {
+                return Err(format!(
+                    "an order can have at most {} items",
+                    MAX_ORDER_ITEMS
+                ));
+            }
+            let mut seen = HashSet::new();
+            let mut sales = Vec::with_capacity(sale_ids.len());
+            for id in &sale_ids {
+                if !seen.insert(*id) {
+                    return Err(format!("sales id={} is given more than once", id));
+                }
+                let sale = _get_sales(id).ok_or(format!("a sales with id={} not found", id))?;
+                if let Some(order_id) = SALE_ORDERS.with(|index| index.borrow().get(id)) {
+                    return Err(format!("Sale {} already in order {}", id, order_id));
+                }
+                if let Some(other) = sale.customer.as_ref().filter(|other| **other != customer) {
+                    return Err(format!(
+                        "sales id={} belongs to customer {}, not {}",
+                        id, other, customer
+                    ));
+                }
+                sales.push(sale);
+            }
+            let order_id = generate_unique_id()?;
+            for mut sale in sales.into_iter().filter(|sale| sale.customer.is_none()) {
+                sale.customer = Some(customer.clone());
+                sale.updated_at = Some(time());
+                do_insert_sales(&sale);
+            }
+            let order = Order {
+                id: order_id,
+                customer,
+                sale_ids,
+                created_at: time(),
+                tenant: Some(yard()),
+                decode_error: None,
+            };
+            do_insert_order(&order);
+            Ok(order)
+        },
+    )
+}
+
+//function to get an order by id together with its sales and the reversals of them, which
+//count negatively towards the total
+#[ic_cdk::query]
+fn get_order(id: u64) -> Result<OrderDetails, String> {
+    match ORDER_STORAGE
+        .with(|service| service.borrow().get(&id))
+        .filter(|order| in_partition(&order.tenant))
+    {
+        Some(order) => {
+            //reversals aren't part of the order, but they take back what its sales brought in
+            let reversals = order.sale_ids.iter().filter_map(|id| reversed_by(*id));
+            let ids = order.sale_ids.iter().copied().chain(reversals).collect();
+            let sales = sort_sales(get_sales_by_ids(ids));
+            let total = sales_total(&sales)?;
+            Ok(OrderDetails {
+                order,
+                sales,
+                total,
+            })
+        }
+        None => Err(format!("an order with id={} not found", id)),
+    }
+}
+
+//function to get the total of an order, the sum of price * quantity over its sales less
+//what has been reversed
+#[ic_cdk::query]
+fn order_total(order_id: u64) -> Result<u64, String> {
+    get_order(order_id).map(|details| details.total)
+}
+
+//function to update a timber
+#[ic_cdk::update]
+fn update_timber(id: u64, payload: TimberUpdatePayload) -> Result<Timber, String> {
+    logged("update_timber", format!("id={}", id), || {
+        check_rate_limit()?;
+        match _get_timber(&id) {
+            Some(Timber {
+                decode_error: Some(e),
+                ..
+            }) => Err(format!(
+                "couldn't update a timber with id={}. it is corrupted: {}",
+                id, e
+            )),
+            Some(mut timber) => {
+                let timber_size = validate_timber_fields(
+                    &payload.timber_type,
+                    &payload.timber_size,
+                    &payload.supplier,
+                )?;
+                check_timber_policy(&payload.supplier, &payload.unit_cost)?;
+                if let Some(unit) = payload.unit {
+                    timber.unit = Some(validate_unit(unit)?);
+                }
+                timber.location = payload.location.map(validate_location).transpose()?;
+                timber.unit_cost = payload.unit_cost;
+                timber.reorder_point = payload.reorder_point;
+                timber.sale_increment = payload.sale_increment;
+                timber.bundle_size = check_bundle_size(payload.bundle_size)?;
+                if let Some(price) = payload.default_unit_price {
+                    check_price(price)?;
+                }
+                timber.default_unit_price = payload.default_unit_price;
+                check_capacity(timber.quantity, payload.quantity, payload.max_capacity)?;
+                timber.max_capacity = payload.max_capacity;
+                timber.enforce_fifo = payload.enforce_fifo;
+                timber.timber_type = payload.timber_type;
+                timber.timber_size = timber_size;
+                let was_low = is_low_stock(&timber);
+                let delta = to_delta(payload.quantity) - to_delta(timber.quantity);
+                let reason = check_change_reason(payload.reason, delta)?;
+                timber.quantity = payload.quantity;
+                timber.supplier = payload.supplier;
+                timber.updated_at = Some(time());
+                do_insert_timber(&timber)?;
+                if delta != 0 {
+                    let reason = match reason {
+                        Some(reason) => format!("update: {}", reason),
+                        None => "update".to_string(),
+                    };
+                    do_record_movement(id, delta, &reason);
+                }
+                if !was_low {
+                    do_notify_low_stock(&timber);
+                }
+                Ok(timber)
+            }
+            None => Err(format!(
+                "couldn't update a timber with id={}. timber not found",
+                id
+            )),
+        }
+    })
+}
+
+//function to update a sales. a change in quantity is taken out of or put back into the
+//timber stock
+#[ic_cdk::update]
+fn update_sales(id: u64, payload: SalesUpdatePayload) -> Result<Sales, String> {
+    logged("update_sales", format!("id={}", id), || {
+        check_rate_limit()?;
+        match _get_sales(&id) {
+            Some(Sales {
+                decode_error: Some(e),
+                ..
+            }) => Err(format!(
+                "couldn't update a sales with id={}. it is corrupted: {}",
+                id, e
+            )),
+            Some(mut sales) => {
+                check_not_reversed(&sales)?;
+                check_price(payload.price)?;
+                check_line_total(payload.quantity, payload.price)?;
+                if payload.quantity > sales.quantity {
+                    check_stock(&[SalesPayload {
+                        timber_id: sales.timber_id,
+                        quantity: payload.quantity - sales.quantity,
+                        ..Default::default()
+                    }])?;
+                }
+                if payload.price != sales.price {
+                    do_record_price_change(&sales, payload.price)?;
+                }
+                match payload.quantity.cmp(&sales.quantity) {
+                    std::cmp::Ordering::Greater => {
+                        let extra = payload.quantity - sales.quantity;
+                        do_deduct_stock(sales.timber_id, extra);
+                        do_use_reservations(sales.timber_id, extra);
+                    }
+                    std::cmp::Ordering::Less => do_restore_stock(
+                        sales.timber_id,
+                        sales.quantity - payload.quantity,
+                        "sale updated",
+                    ),
+                    std::cmp::Ordering::Equal => {}
+                }
+                sales.quantity = payload.quantity;
+                sales.price = payload.price;
+                sales.updated_at = Some(time());
+                do_insert_sales(&sales);
+                Ok(sales)
+            }
+            None => Err(format!(
+                "couldn't update a sales with id={}. sales not found",
+                id
+            )),
+        }
+    })
+}
+
+//function to delete a timber
+#[ic_cdk::update]
+fn delete_timber(id: u64) -> Result<Timber, String> {
+    logged("delete_timber", format!("id={}", id), || {
+        check_rate_limit()?;
+        match _get_timber(&id) {
+            Some(timber) => {
+                do_remove_timber(&timber);
+                Ok(timber)
+            }
+            None => Err(format!(
+                "couldn't delete a timber with id={}. timber not found",
+                id
+            )),
+        }
+    })
+}
+
+//function to set how long after it was made a sale can be deleted by anyone allowed to
+//delete it. older sales can only be deleted by the owner. 0 turns the limit off. owner only
+#[ic_cdk::update]
+fn set_delete_grace_ns(grace_ns: u64) -> Result<(), String> {
+    logged(
+        "set_delete_grace_ns",
+        format!("grace_ns={}", grace_ns),
+        || {
+            ensure_owner()?;
+            set_setting(&DELETE_GRACE_NS, grace_ns)
+        },
+    )
+}
+
+//function to get how long after it was made a sale can be deleted by anyone, 0 for no limit
+#[ic_cdk::query]
+fn get_delete_grace_ns() -> u64 {
+    get_setting(&DELETE_GRACE_NS)
+}
+
+//function to cancel several sales at once, as delete_sales would, with one reason recorded
+//on the stock movements. repeated ids are cancelled once and each id gets its own outcome,
+//so one failing sales doesn't stop the rest. sales already in the recycle bin are reported
+//rather than having their stock put back twice
+#[ic_cdk::update]
+fn bulk_cancel_sales(ids: Vec<u64>, reason: String) -> Result<Vec<CancelOutcome>, String> {
+    logged(
+        "bulk_cancel_sales",
+        format!("ids={} reason={}", ids.len(), reason),
+        || {
+            check_rate_limit()?;
+            let Some(reason) = check_change_reason(Some(reason), 0)? else {
+                return Err("Reason must not be empty".to_string());
+            };
+            if ids.len() > MAX_ORDER_ITEMS {
+                return Err(format!(
+                    "at most {} sales can be cancelled at once",
+                    MAX_ORDER_ITEMS
+                ));
+            }
+            let reason = format!("sale cancelled: {}", reason);
+            let mut seen = HashSet::new();
+            Ok(ids
+                .into_iter()
+                .filter(|id| seen.insert(*id))
+                .map(|id| (id, do_cancel_sales(id, &reason)))
+                .collect())
+        },
+    )
+}
+
+//function to reverse a sales, for bookkeeping that keeps every entry. the sales is left as
+//it is and a reversal entry with the same quantity and price is created, which revenue and
+//units sold count negatively. the quantity goes back into the timber stock. a sales can be
+//reversed once, and neither it nor its reversal can be changed or deleted afterwards
+#[ic_cdk::update]
+fn reverse_sale(id: u64) -> Result<Sales, String> {
+    logged("reverse_sale", format!("id={}", id), || {
+        check_rate_limit()?;
+        let sales = get_sales(id)?;
+        check_not_reversed(&sales)?;
+        check_record_limit(sales_count(), 1, get_setting(&MAX_SALES_RECORDS))?;
+        let reversal = Sales {
+            id: generate_unique_id()?,
+            created_at: time(),
+            updated_at: None,
+            tenant: Some(yard()),
+            is_reversal: Some(true),
+            reversal_of: Some(id),
+            //the reversal is a correction, not part of the order the sales is in
+            order_id: None,
+            ..sales
+        };
+        do_insert_sales(&reversal);
+        do_index_reversal(&reversal);
+        do_restore_stock(reversal.timber_id, reversal.quantity, "sale reversed");
+        Ok(reversal)
+    })
+}
+
+//function to delete a sales. the sales is moved to the recycle bin and its quantity
+//goes back into the timber stock, see restore_sales
+#[ic_cdk::update]
+fn delete_sales(id: u64) -> Result<Sales, String> {
+    logged("delete_sales", format!("id={}", id), || {
+        check_rate_limit()?;
+        match _get_sales(&id) {
+            Some(sales) => {
+                check_not_reversed(&sales)?;
+                check_delete_grace(&sales)?;
+                do_delete_sales(&sales, "sale deleted");
+                Ok(sales)
+            }
+            None => Err(format!(
+                "couldn't delete a sales with id={}. sales not found",
+                id
+            )),
+        }
+    })
+}
+
+
+//function to list the distinct supplier names across the caller's timber, sorted.
+//names are compared case-insensitively and the casing of the first occurrence is kept
+#[ic_cdk::query]
+fn list_suppliers() -> Vec<String> {
+    let mut suppliers: BTreeMap<String, String> = BTreeMap::new();
+    for timber in partition_timber() {
+        if let Some(supplier) = timber.supplier {
+            suppliers.entry(supplier.to_lowercase()).or_insert(supplier);
+        }
+    }
+    suppliers.into_values().collect()
+}
+
+//function to list a page of timber, each paired with the number of sales referencing it,
+//a reversal taking one off as in sales_count_by_timber. pages are taken in id order
+#[ic_cdk::query]
+fn list_timber_with_sales_count(offset: u64, limit: u64) -> Vec<(Timber, u64)> {
+    let mut net_counts: HashMap<u64, i64> = HashMap::new();
+    for sales in partition_sales() {
+        *net_counts.entry(sales.timber_id).or_insert(0) += net_count(&sales);
+    }
+    let sales_count: HashMap<u64, u64> = net_counts
+        .into_iter()
+        .map(|(timber_id, count)| (timber_id, count.max(0) as u64))
+        .collect();
+    partition_timber()
+        .into_iter()
+        .skip(offset as usize)
+        .take(limit.min(MAX_PAGE_SIZE) as usize)
+        .map(|timber| {
+            let count = sales_count.get(&timber.id).copied().unwrap_or(0);
+            (timber, count)
+        })
+        .collect()
+}
+
+//function to restore a deleted sales from the recycle bin. the quantity is deducted
+//from the timber stock again, so this fails if there is no longer enough stock. the sales
+//counts towards the sales record limit again
+#[ic_cdk::update]
+fn restore_sales(id: u64) -> Result<Sales, String> {
+    logged("restore_sales", format!("id={}", id), || {
+        check_rate_limit()?;
+        match DELETED_SALES
+            .with(|service| service.borrow().get(&id))
+            .filter(|sales| in_partition(&sales.tenant))
+        {
+            Some(sales) => {
+                check_record_limit(sales_count(), 1, get_setting(&MAX_SALES_RECORDS))?;
+                check_stock(&[SalesPayload {
+                    timber_id: sales.timber_id,
+                    quantity: sales.quantity,
+                    ..Default::default()
+                }])?;
+                do_deduct_stock(sales.timber_id, sales.quantity);
+                DELETED_SALES.with(|service| service.borrow_mut().remove(&id));
+                do_insert_sales(&sales);
+                Ok(sales)
+            }
+            None => Err(format!(
+                "couldn't restore a sales with id={}. sales not found in the recycle bin",
+                id
+            )),
+        }
+    })
+}
+
+//function to list the deleted sales that can be restored
+#[ic_cdk::query]
+fn list_deleted_sales() -> Vec<Sales> {
+    sort_sales(DELETED_SALES.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .filter(|(_, sales)| in_partition(&sales.tenant))
+            .map(|(_, sales)| sales)
+            .collect()
+    }))
+}
+
+//function to set the maximum number of timber and sales records, 0 means unlimited. owner only
+#[ic_cdk::update]
+fn set_record_limits(max_timber_records: u64, max_sales_records: u64) -> Result<(), String> {
+    logged(
+        "set_record_limits",
+        format!(
+            "max_timber_records={} max_sales_records={}",
+            max_timber_records, max_sales_records
+        ),
+        || {
+            ensure_owner()?;
+            set_setting(&MAX_TIMBER_RECORDS, max_timber_records)?;
+            set_setting(&MAX_SALES_RECORDS, max_sales_records)
+        },
+    )
+}
+
+//function to get the current record counts and their limits
+#[ic_cdk::query]
+fn get_record_usage() -> RecordUsage {
+    RecordUsage {
+        timber_count: timber_count(),
+        max_timber_records: get_setting(&MAX_TIMBER_RECORDS),
+        sales_count: sales_count(),
+        max_sales_records: get_setting(&MAX_SALES_RECORDS),
+    }
+}
+
+//function to get the timber with the given ids in the order they were asked for,
+//unlike the other queries which return records sorted by id.
+//missing ids are skipped and repeated ids are returned once
+#[ic_cdk::query]
+fn get_timber_by_ids(ids: Vec<u64>) -> Vec<Timber> {
+    let mut seen = HashSet::new();
+    ids.into_iter()
+        .filter(|id| seen.insert(*id))
+        .filter_map(|id| _get_timber(&id))
+        .collect()
+}
+
+//function to get the sales with the given ids in the order they were asked for,
+//unlike the other queries which return records sorted by id.
+//missing ids are skipped and repeated ids are returned once
+#[ic_cdk::query]
+fn get_sales_by_ids(ids: Vec<u64>) -> Vec<Sales> {
+    let mut seen = HashSet::new();
+    ids.into_iter()
+        .filter(|id| seen.insert(*id))
+        .filter_map(|id| _get_sales(&id))
+        .collect()
+}
+
+//function to set the business hours sales are allowed in, as nanoseconds into the utc day.
+//a window with start > end wraps around midnight and start == end disables the check. owner only
+#[ic_cdk::update]
+fn set_business_hours(start_ns: u64, end_ns: u64) -> Result<(), String> {
+    logged(
+        "set_business_hours",
+        format!("start_ns={} end_ns={}", start_ns, end_ns),
+        || {
+            ensure_owner()?;
+            check_business_hours_setting(start_ns, end_ns)?;
+            set_setting(&BUSINESS_HOURS_START_NS, start_ns)?;
+            set_setting(&BUSINESS_HOURS_END_NS, end_ns)
+        },
+    )
+}
+
+//function to top a timber up to target_quantity, recording the difference as an intake.
+//the target must be above the current quantity, this never takes stock away
+#[ic_cdk::update]
+fn restock_to_level(id: u64, target_quantity: u64) -> Result<Timber, String> {
+    logged(
+        "restock_to_level",
+        format!("id={} target_quantity={}", id, target_quantity),
+        || {
+            check_rate_limit()?;
+            let mut timber = get_timber(id)?;
+            if target_quantity <= timber.quantity {
+                return Err(format!(
+                    "target quantity {} must be greater than the current quantity {}",
+                    target_quantity, timber.quantity
+                ));
+            }
+            check_capacity(timber.quantity, target_quantity, timber.max_capacity)?;
+            let delta = to_delta(target_quantity) - to_delta(timber.quantity);
+            timber.quantity = target_quantity;
+            timber.updated_at = Some(time());
+            do_insert_timber(&timber)?;
+            do_record_movement(id, delta, "intake");
+            Ok(timber)
+        },
+    )
+}
+
+//function to copy a timber into a new record with a fresh id and the given quantity.
+//the sku is not copied since it must stay unique
+#[ic_cdk::update]
+fn clone_timber(source_id: u64, new_quantity: u64) -> Result<Timber, String> {
+    logged(
+        "clone_timber",
+        format!("source_id={} new_quantity={}", source_id, new_quantity),
+        || {
+            check_rate_limit()?;
+            let source = get_timber(source_id)?;
+            check_record_limit(timber_count(), 1, get_setting(&MAX_TIMBER_RECORDS))?;
+            check_capacity(0, new_quantity, source.max_capacity)?;
+            let timber = Timber {
+                id: generate_unique_id()?,
+                quantity: new_quantity,
+                sku: None,
+                created_at: time(),
+                updated_at: None,
+                ..source
+            };
+            do_insert_timber(&timber)?;
+            do_record_movement(timber.id, to_delta(timber.quantity), "intake");
+            Ok(timber)
+        },
+    )
+}
+
+//function to split part of a timber off into a new record with the same attributes, apart
+//from the sku which stays with the original. the original keeps the rest of the quantity,
+//so both records end up with some stock
+#[ic_cdk::update]
+fn split_timber(id: u64, split_quantity: u64) -> Result<(Timber, Timber), String> {
+    logged(
+        "split_timber",
+        format!("id={} split_quantity={}", id, split_quantity),
+        || {
+            check_rate_limit()?;
+            let mut original = get_timber(id)?;
+            if split_quantity == 0 || split_quantity >= original.quantity {
+                return Err(format!(
+                    "split quantity must be greater than 0 and less than the timber quantity of {}",
+                    original.quantity
+                ));
+            }
+            check_record_limit(timber_count(), 1, get_setting(&MAX_TIMBER_RECORDS))?;
+            let now = time();
+            let split = Timber {
+                id: generate_unique_id()?,
+                quantity: split_quantity,
+                sku: None,
+                created_at: now,
+                updated_at: None,
+                ..original.clone()
+            };
+            original.quantity -= split_quantity;
+            original.updated_at = Some(now);
+            check_timber_size(&original)?;
+            do_insert_timber(&split)?;
+            do_insert_timber(&original)?;
+            do_record_movement(original.id, -to_delta(split_quantity), "split");
+            do_record_movement(split.id, to_delta(split_quantity), "split");
+            Ok((original, split))
+        },
+    )
+}
+
+//function to add a unit timber can be counted in. owner only
+#[ic_cdk::update]
+fn add_valid_unit(unit: String) -> Result<(), String> {
+    logged("add_valid_unit", format!("unit={}", unit), || {
+        ensure_owner()?;
+        set_insert(&VALID_UNITS, unit)
+    })
+}
+
+//function to remove a unit. a unit still used by a timber can't be removed. owner only
+#[ic_cdk::update]
+fn remove_valid_unit(unit: String) -> Result<(), String> {
+    logged("remove_valid_unit", format!("unit={}", unit), || {
+        ensure_owner()?;
+        if any_timber(|timber| timber.unit.as_deref().unwrap_or(DEFAULT_UNIT) == unit) {
+            return Err(format!("unit {} is still in use", unit));
+        }
+        set_remove(&VALID_UNITS, &unit)
+    })
+}
+
+//function to list the units timber can be counted in
+#[ic_cdk::query]
+fn list_valid_units() -> Vec<String> {
+    set_values(&VALID_UNITS)
+}
+
+//function to add a location timber can be kept in. owner only
+#[ic_cdk::update]
+fn add_valid_location(location: String) -> Result<(), String> {
+    logged(
+        "add_valid_location",
+        format!("location={}", location),
+        || {
+            ensure_owner()?;
+            set_insert(&VALID_LOCATIONS, location)
+        },
+    )
+}
+
+//function to remove a location. a location still used by a timber can't be removed. owner only
+#[ic_cdk::update]
+fn remove_valid_location(location: String) -> Result<(), String> {
+    logged(
+        "remove_valid_location",
+        format!("location={}", location),
+        || {
+            ensure_owner()?;
+            if any_timber(|timber| timber.location.as_deref() == Some(location.as_str())) {
+                return Err(format!("location {} is still in use", location));
+            }
+            set_remove(&VALID_LOCATIONS, &location)
+        },
+    )
+}
+
+//function to list the locations timber can be kept in
+#[ic_cdk::query]
+fn list_valid_locations() -> Vec<String> {
+    set_values(&VALID_LOCATIONS)
+}
+
+//function to get the number of sales, units sold and revenue of a timber, reversals taking
+//off what the sales they reverse added
+#[ic_cdk::query]
+fn sales_summary_for_timber(timber_id: u64) -> Result<SalesSummary, String> {
+    if _get_timber(&timber_id).is_none() {
+        return Err(format!("a timber with id={} not found", timber_id));
+    }
+    let sales = _get_sales_by_timber_id(&timber_id);
+    let units_sold: i128 = sales.iter().map(net_quantity).sum();
+    let units_sold =
+        u64::try_from(units_sold.max(0)).map_err(|_| "units sold overflows".to_string())?;
+    let revenue = sales_total(&sales)?;
+    let average_price = if units_sold == 0 {
+        0
+    } else {
+        apply_rounding(revenue as u128, units_sold as u128, rounding_mode()) as u64
+    };
+    let transactions: i64 = sales.iter().map(net_count).sum();
+    Ok(SalesSummary {
+        transactions: transactions.max(0) as u64,
+        units_sold,
+        revenue,
+        average_price,
+    })
+}
+
+//function to find the timber whose type starts with the given prefix, ignoring case.
+//an empty or overly long prefix matches nothing rather than scanning for everything
+#[ic_cdk::query]
+fn search_timber_by_prefix(prefix: String) -> Vec<Timber> {
+    let prefix = prefix.trim().to_lowercase();
+    if prefix.is_empty() || prefix.len() > MAX_SEARCH_LENGTH {
+        return Vec::new();
+    }
+    partition_timber()
+        .into_iter()
+        .filter(|timber| timber.timber_type.to_lowercase().starts_with(&prefix))
+        .collect()
+}
+
+//function to delete every timber in the owner's yard, returning how many were removed.
+//owner only and only when called with the confirmation token, to guard against accidental calls
+#[ic_cdk::update]
+fn delete_all_timber(confirm: String) -> Result<u64, String> {
+    logged("delete_all_timber", String::new(), || {
+        ensure_owner()?;
+        if confirm != DELETE_ALL_TIMBER_CONFIRMATION {
+            return Err("Confirmation token mismatch".to_string());
+        }
+        let timber = partition_timber();
+        for timber in &timber {
+            do_remove_timber(timber);
+        }
+        Ok(timber.len() as u64)
+    })
+}
+
+//function to get the most recent sale of a timber, none if it has never sold
+#[ic_cdk::query]
+fn latest_sale_for_timber(timber_id: u64) -> Option<Sales> {
+    _get_timber(&timber_id)?;
+    _get_sales_by_timber_id(&timber_id)
+        .into_iter()
+        .max_by_key(|sales| (sales.created_at, sales.id))
+}
+
+//function to get the created_at of the first and the last sale of a timber.
+//reversals are corrections rather than sales, so they don't widen the range
+#[ic_cdk::query]
+fn sale_date_range(timber_id: u64) -> Result<(u64, u64), String> {
+    _get_timber(&timber_id).ok_or_else(|| format!("timber with id={} not found", timber_id))?;
+    _get_sales_by_timber_id(&timber_id)
+        .iter()
+        .filter(|sales| !is_reversal(sales))
+        .fold(None, |range, sales| match range {
+            None => Some((sales.created_at, sales.created_at)),
+            Some((first, last)) => Some((
+                u64::min(first, sales.created_at),
+                u64::max(last, sales.created_at),
+            )),
+        })
+        .ok_or_else(|| format!("timber with id={} has never sold", timber_id))
+}
+
+//function to get the stock figures of every timber type in stock, most valuable type first
+#[ic_cdk::query]
+fn inventory_report() -> Vec<TypeReport> {
+    let mut reports: BTreeMap<String, TypeReport> = BTreeMap::new();
+    for timber in partition_timber() {
+        let report = reports
+            .entry(timber.timber_type.clone())
+            .or_insert_with(|| TypeReport {
+                timber_type: timber.timber_type.clone(),
+                ..Default::default()
+            });
+        report.total_quantity = report.total_quantity.saturating_add(timber.quantity);
+        report.total_value = report.total_value.saturating_add(timber_value(&timber));
+        report.record_count += 1;
+        if is_low_stock(&timber) {
+            report.low_stock_count += 1;
+        }
+    }
+    let mut reports: Vec<TypeReport> = reports
+        .into_values()
+        .filter(|report| report.total_quantity > 0)
+        .collect();
+    reports.sort_by_key(|report| std::cmp::Reverse(report.total_value));
+    reports
+}
+
+//function to set how many update calls a caller can make per minute, 0 means unlimited.
+//the owner is never rate limited. owner only
+#[ic_cdk::update]
+fn set_rate_limit(calls_per_minute: u64) -> Result<(), String> {
+    logged(
+        "set_rate_limit",
+        format!("calls_per_minute={}", calls_per_minute),
+        || {
+            ensure_owner()?;
+            check_rate_limit_setting(calls_per_minute)?;
+            set_setting(&RATE_LIMIT, calls_per_minute)
+        },
+    )
+}
+
+//function to count the timber created between start_ns and end_ns in buckets of bucket_ns,
+//returning (bucket start, count) for every bucket that has timber in it
+#[ic_cdk::query]
+fn timber_intake_histogram(
+    start_ns: u64,
+    end_ns: u64,
+    bucket_ns: u64,
+) -> Result<Vec<(u64, u64)>, String> {
+    if bucket_ns == 0 {
+        return Err("bucket_ns must be greater than 0".to_string());
+    }
+    if start_ns > end_ns {
+        return Err("start_ns must not be after end_ns".to_string());
+    }
+    let mut buckets: BTreeMap<u64, u64> = BTreeMap::new();
+    for timber in partition_timber() {
+        if timber.created_at >= start_ns && timber.created_at <= end_ns {
+            let bucket_start = start_ns + (timber.created_at - start_ns) / bucket_ns * bucket_ns;
+            *buckets.entry(bucket_start).or_insert(0) += 1;
+        }
+    }
+    Ok(buckets.into_iter().collect())
+}
+
+//function to get what the current stock would sell for. each timber is valued at the price
+//of its latest sale, timber that has never sold falls back to its unit cost and is skipped
+//when it has none. summed as u128 so large catalogs can't overflow
+#[ic_cdk::query]
+fn total_potential_revenue() -> u128 {
+    let mut latest_prices: HashMap<u64, (u64, u64, u64)> = HashMap::new();
+    for sales in partition_sales() {
+        let key = (sales.created_at, sales.id, sales.price);
+        let latest = latest_prices.entry(sales.timber_id).or_insert(key);
+        if key > *latest {
+            *latest = key;
+        }
+    }
+    partition_timber()
+        .into_iter()
+        .filter_map(|timber| {
+            let price = latest_prices
+                .get(&timber.id)
+                .map(|(_, _, price)| *price)
+                .or(timber.unit_cost)?;
+            Some(amount(timber.quantity, price, timber.quantity_decimals))
+        })
+        .sum()
+}
+
+//function to get a timber by its sku
+#[ic_cdk::query]
+fn get_timber_by_sku(sku: String) -> Result<Timber, String> {
+    match SKU_INDEX.with(|index| index.borrow().get(&sku_key(yard(), &sku))) {
+        Some(id) => get_timber(id),
+        None => Err(format!("a timber with sku={} not found", sku)),
+    }
+}
+
+//function to get the sales of a customer created between start_ns and end_ns, newest first.
+//deleted sales sit in the recycle bin and are not included
+#[ic_cdk::query]
+fn customer_sales_in_range(customer: String, start_ns: u64, end_ns: u64) -> Vec<Sales> {
+    let mut sales: Vec<Sales> = partition_sales()
+        .into_iter()
+        .filter(|sales| {
+            sales.customer.as_deref() == Some(customer.as_str())
+                && sales.created_at >= start_ns
+                && sales.created_at <= end_ns
+        })
+        .collect();
+    sales.sort_by_key(|sales| std::cmp::Reverse((sales.created_at, sales.id)));
+    sales
+}
+
+//function to get a page of the sales of a customer, newest first, with the number of sales
+//the customer has in all. at most MAX_PAGE_SIZE sales are returned. cancelled sales sit in the
+//recycle bin and are not included
+#[ic_cdk::query]
+fn customer_sales_page(customer: String, offset: u64, limit: u64) -> (Vec<Sales>, u64) {
+    let mut sales: Vec<Sales> = partition_sales()
+        .into_iter()
+        .filter(|sales| sales.customer.as_deref() == Some(customer.as_str()))
+        .collect();
+    sales.sort_by_key(|sales| std::cmp::Reverse((sales.created_at, sales.id)));
+    let total = sales.len() as u64;
+    let page = sales
+        .into_iter()
+        .skip(offset as usize)
+        .take(limit.min(MAX_PAGE_SIZE) as usize)
+        .collect();
+    (page, total)
+}
+
+//function to set how divisions in price calculations are rounded. owner only
+#[ic_cdk::update]
+fn set_rounding_mode(mode: RoundingMode) -> Result<(), String> {
+    logged("set_rounding_mode", format!("mode={:?}", mode), || {
+        ensure_owner()?;
+        set_setting(&ROUNDING_MODE, mode.to_setting())
+    })
+}
+
+//function to get how divisions in price calculations are rounded
+#[ic_cdk::query]
+fn get_rounding_mode() -> RoundingMode {
+    rounding_mode()
+}
+
+//function to set the lowest and highest price a sale can have, 0 means no limit. owner only
+#[ic_cdk::update]
+fn set_price_limits(min_price: u64, max_price: u64) -> Result<(), String> {
+    logged(
+        "set_price_limits",
+        format!("min_price={} max_price={}", min_price, max_price),
+        || {
+            ensure_owner()?;
+            check_price_limits_setting(min_price, max_price)?;
+            set_setting(&MIN_PRICE, min_price)?;
+            set_setting(&MAX_PRICE, max_price)
+        },
+    )
+}
+
+//function to set the price of every sale of a timber, returning how many were changed.
+//deleted sales in the recycle bin are left alone. owner only
+#[ic_cdk::update]
+fn bulk_update_price(timber_id: u64, new_price: u64) -> Result<u64, String> {
+    logged(
+        "bulk_update_price",
+        format!("timber_id={} new_price={}", timber_id, new_price),
+        || {
+            ensure_owner()?;
+            check_price(new_price)?;
+            if _get_timber(&timber_id).is_none() {
+                return Err(format!("a timber with id={} not found", timber_id));
+            }
+            //a reversal must keep the price of the sales it reverses, so both are left alone
+            let sales: Vec<Sales> = _get_sales_by_timber_id(&timber_id)
+                .into_iter()
+                .filter(|sales| check_not_reversed(sales).is_ok())
+                .collect();
+            for sales in &sales {
+                check_line_total(sales.quantity, new_price)?;
+            }
+            let now = time();
+            let mut updated = 0;
+            for mut sales in sales {
+                if sales.price == new_price {
+                    continue;
+                }
+                do_record_price_change(&sales, new_price)?;
+                sales.price = new_price;
+                sales.updated_at = Some(now);
+                do_insert_sales(&sales);
+                updated += 1;
+            }
+            Ok(updated)
+        },
+    )
+}
+
+//function to change the default unit price of every timber by percent_bps basis points,
+//e.g. 250 for +2.5% or -1000 for -10%, rounded with the rounding mode and kept within the
+//price limits. every change is recorded in the default price history and the number of
+//timber changed is returned. owner only
+#[ic_cdk::update]
+fn adjust_default_prices(percent_bps: i64) -> Result<u64, String> {
+    logged(
+        "adjust_default_prices",
+        format!("percent_bps={}", percent_bps),
+        || {
+            ensure_owner()?;
+            let now = time();
+            //every new price is worked out before any is written, so an error leaves all
+            //the prices as they were
+            let mut changes = Vec::new();
+            for mut timber in partition_timber() {
+                let Some(old_price) = timber.default_unit_price else {
+                    continue;
+                };
+                let new_price = adjusted_price(old_price, percent_bps)?;
+                if new_price == old_price {
+                    continue;
+                }
+                timber.default_unit_price = Some(new_price);
+                timber.updated_at = Some(now);
+                check_timber_size(&timber)?;
+                changes.push((timber, old_price, new_price));
+            }
+            let ids = changes
+                .iter()
+                .map(|_| generate_unique_id())
+                .collect::<Result<Vec<u64>, String>>()?;
+            for (id, (timber, old_price, new_price)) in ids.into_iter().zip(&changes) {
+                do_insert_timber(timber)?;
+                let change = DefaultPriceChange {
+                    id,
+                    timber_id: timber.id,
+                    old_price: *old_price,
+                    new_price: *new_price,
+                    changed_at: now,
+                };
+                DEFAULT_PRICE_HISTORY
+                    .with(|service| service.borrow_mut().insert(change.id, change));
+            }
+            Ok(changes.len() as u64)
+        },
+    )
+}
+
+//function to get the default unit price changes of a timber, oldest first
+#[ic_cdk::query]
+fn get_default_price_history(timber_id: u64) -> Vec<DefaultPriceChange> {
+    if _get_timber(&timber_id).is_none() {
+        return Vec::new();
+    }
+    DEFAULT_PRICE_HISTORY.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .filter(|(_, change)| change.timber_id == timber_id)
+            .map(|(_, change)| change)
+            .collect()
+    })
+}
+
+//function to get the price changes of a sales, oldest first. sales in the recycle bin
+//keep their history
+#[ic_cdk::query]
+fn get_price_history(sales_id: u64) -> Vec<PriceChange> {
+    let visible = _get_sales(&sales_id).is_some()
+        || DELETED_SALES.with(|service| {
+            matches!(service.borrow().get(&sales_id), Some(sales) if in_partition(&sales.tenant))
+        });
+    if !visible {
+        return Vec::new();
+    }
+    PRICE_HISTORY.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .filter(|(_, change)| change.sales_id == sales_id)
+            .map(|(_, change)| change)
+            .collect()
+    })
+}
+
+//function to get the stock movements of a timber, oldest first. the movements of deleted
+//timber can't be told apart from another yard's and are not returned
+#[ic_cdk::query]
+fn get_stock_movements(timber_id: u64) -> Vec<StockMovement> {
+    if _get_timber(&timber_id).is_none() {
+        return Vec::new();
+    }
+    STOCK_MOVEMENTS.with(|service| {
+        service
+            .borrow()
+            .range((timber_id, 0)..=(timber_id, u64::MAX))
+            .map(|(_, movement)| movement)
+            .collect()
+    })
+}
+
+//function to reconstruct the quantity a timber had at the given time by replaying its stock
+//movements. stock that came in before movements were recorded is missing from the result,
+//which is signed so such inconsistencies show up rather than wrapping around
+#[ic_cdk::query]
+fn stock_as_of(timber_id: u64, timestamp_ns: u64) -> Result<i64, String> {
+    if _get_timber(&timber_id).is_none() {
+        return Err(format!("a timber with id={} not found", timber_id));
+    }
+    let movements = get_stock_movements(timber_id);
+    Ok(movements
+        .iter()
+        .filter(|movement| movement.created_at <= timestamp_ns)
+        .fold(0i64, |quantity, movement| {
+            quantity.saturating_add(movement.delta)
+        }))
+}
+
+//function to estimate how many days the stock of a timber lasts at the rate it sold over
+//the last lookback_days, rounded down. u64::MAX means it didn't sell in that window
+#[ic_cdk::query]
+fn days_of_stock_remaining(timber_id: u64, lookback_days: u64) -> Result<u64, String> {
+    if lookback_days == 0 {
+        return Err("lookback_days must be greater than 0".to_string());
+    }
+    let timber = get_timber(timber_id)?;
+    let since = time().saturating_sub(lookback_days.saturating_mul(DAY_NS));
+    let units_sold: i128 = _get_sales_by_timber_id(&timber_id)
+        .iter()
+        .filter(|sales| sales.created_at >= since)
+        .map(net_quantity)
+        .sum();
+    let units_sold = units_sold.max(0) as u128;
+    if units_sold == 0 {
+        return Ok(u64::MAX);
+    }
+    let days = timber.quantity as u128 * lookback_days as u128 / units_sold;
+    Ok(days.min(u64::MAX as u128) as u64)
+}
+
+//function to check a timber payload the way add_timber would, without storing anything
+#[ic_cdk::query]
+fn validate_timber_payload(p: TimberPayload) -> Result<(), String> {
+    check_timber_payload(p).map(|_| ())
+}
+
+//function to check a sales payload the way add_sales would, without storing anything
+#[ic_cdk::query]
+fn validate_sales_payload(p: SalesPayload) -> Result<(), String> {
+    check_sales_payload(p).map(|_| ())
+}
+
+//function to get the sales of every timber that has been sold, grouped by timber id.
+//groups are sorted by timber id and the sales in a group by id
+#[ic_cdk::query]
+fn sales_grouped_by_timber() -> Vec<(u64, Vec<Sales>)> {
+    let mut groups: BTreeMap<u64, Vec<Sales>> = BTreeMap::new();
+    for sales in partition_sales() {
+        groups.entry(sales.timber_id).or_default().push(sales);
+    }
+    groups.into_iter().collect()
+}
+
+//function to get how many sales every sold timber has, as (timber id, sale count) sorted by
+//timber id. a reversal takes one off the count, and timber whose sales have all been
+//reversed is left out. cheaper than sales_grouped_by_timber when only the counts are needed
+#[ic_cdk::query]
+fn sales_count_by_timber() -> Vec<(u64, u64)> {
+    let tenant = yard();
+    let mut counts: HashMap<u64, i64> = HashMap::new();
+    SALES_STORAGE.with(|service| {
+        for (_, sales) in service.borrow().iter() {
+            if record_tenant(&sales.tenant) == tenant {
+                *counts.entry(sales.timber_id).or_default() += net_count(&sales);
+            }
+        }
+    });
+    let mut counts: Vec<(u64, u64)> = counts
+        .into_iter()
+        .filter(|(_, count)| *count > 0)
+        .map(|(timber_id, count)| (timber_id, count as u64))
+        .collect();
+    counts.sort_unstable();
+    counts
+}
+
+//function to set the abc_classification thresholds as cumulative shares of stock value in
+//percent, e.g. 70 and 90 for 70% in A, the next 20% in B and the rest in C. owner only
+#[ic_cdk::update]
+fn set_abc_thresholds(a_percent: u64, b_percent: u64) -> Result<(), String> {
+    logged(
+        "set_abc_thresholds",
+        format!("a_percent={} b_percent={}", a_percent, b_percent),
+        || {
+            ensure_owner()?;
+            check_abc_thresholds_setting(a_percent, b_percent)?;
+            set_setting(&ABC_A_PERCENT, a_percent)?;
+            set_setting(&ABC_B_PERCENT, b_percent)
+        },
+    )
+}
+
+//function to get the abc_classification thresholds as (a_percent, b_percent)
+#[ic_cdk::query]
+fn get_abc_thresholds() -> (u64, u64) {
+    (get_setting(&ABC_A_PERCENT), get_setting(&ABC_B_PERCENT))
+}
+
+//function to class every timber as A, B or C by its stock value, quantity * unit_cost, most
+//valuable first. going down the list, timber is A while the value before it is under the
+//a threshold share of the total (70% by default), then B while under the b threshold
+//(90% by default), then C. timber without value is always C
+#[ic_cdk::query]
+fn abc_classification() -> Vec<(u64, AbcClass)> {
+    let mut values: Vec<(u64, u128)> = partition_timber()
+        .iter()
+        .map(|timber| (timber.id, timber_value(timber) as u128))
+        .collect();
+    values.sort_by_key(|(id, value)| (std::cmp::Reverse(*value), *id));
+    let total: u128 = values.iter().map(|(_, value)| value).sum();
+    let a_percent = get_setting(&ABC_A_PERCENT) as u128;
+    let b_percent = get_setting(&ABC_B_PERCENT) as u128;
+    let mut cumulative: u128 = 0;
+    values
+        .into_iter()
+        .map(|(id, value)| {
+            let class = if value == 0 {
+                AbcClass::C
+            } else if cumulative * 100 < a_percent * total {
+                AbcClass::A
+            } else if cumulative * 100 < b_percent * total {
+                AbcClass::B
+            } else {
+                AbcClass::C
+            };
+            cumulative += value;
+            (id, class)
+        })
+        .collect()
+}
+
+//function to get the timber of a type. an invalid type is an error while a valid type
+//without timber gives an empty list, and the same goes for the other type/size queries
+#[ic_cdk::query]
+fn get_timber_by_type(timber_type: String) -> Result<Vec<Timber>, String> {
+    validate_timber_type(&timber_type)?;
+    Ok(partition_timber()
+        .into_iter()
+        .filter(|timber| timber.timber_type == timber_type)
+        .collect())
+}
+
+//function to get the timber of a size
+#[ic_cdk::query]
+fn get_timber_by_size(timber_size: String) -> Result<Vec<Timber>, String> {
+    let timber_size = validate_timber_size(&timber_size)?;
+    Ok(partition_timber()
+        .into_iter()
+        .filter(|timber| timber.timber_size == timber_size)
+        .collect())
+}
+
+//function to get the timber of a type and size
+#[ic_cdk::query]
+fn get_timber_by_type_and_size(
+    timber_type: String,
+    timber_size: String,
+) -> Result<Vec<Timber>, String> {
+    validate_timber_type(&timber_type)?;
+    let timber_size = validate_timber_size(&timber_size)?;
+    Ok(partition_timber()
+        .into_iter()
+        .filter(|timber| timber.timber_type == timber_type && timber.timber_size == timber_size)
+        .collect())
+}
+
+//function to renumber the timber from 1 in id order, returning the highest new id. sales,
+//deleted sales, stock movements, reservations, pending adjustments, default price history
+//and the sku index are updated to match and the id counter carries on after the highest id
+//still in use by any record. references to timber that no longer exists get fresh ids past
+//that point, so they can't end up pointing at other timber.
+//everything happens in this one call, so a trap leaves the old ids untouched. owner only
+#[ic_cdk::update]
+fn compact_timber_ids() -> Result<u64, String> {
+    logged("compact_timber_ids", String::new(), || {
+        ensure_owner()?;
+        let timber: Vec<Timber> = TIMBER_STORAGE
+            .with(|service| service.borrow().iter().map(|(_, timber)| timber).collect());
+        let sales: Vec<Sales> =
+            SALES_STORAGE.with(|service| service.borrow().iter().map(|(_, sales)| sales).collect());
+        let deleted: Vec<Sales> =
+            DELETED_SALES.with(|service| service.borrow().iter().map(|(_, sales)| sales).collect());
+        //a record that failed to decode has lost its ids and rewriting it would lose its bytes
+        if timber.iter().any(|timber| timber.decode_error.is_some())
+            || sales
+                .iter()
+                .chain(&deleted)
+                .any(|sales| sales.decode_error.is_some())
+        {
+            return Err("cannot compact ids while some records are corrupted".to_string());
+        }
+        let movements: Vec<((u64, u64), StockMovement)> =
+            STOCK_MOVEMENTS.with(|service| service.borrow().iter().collect());
+        let reservations: Vec<Reservation> = RESERVATIONS.with(|service| {
+            service
+                .borrow()
+                .iter()
+                .map(|(_, reservation)| reservation)
+                .collect()
+        });
+        let price_changes: Vec<DefaultPriceChange> = DEFAULT_PRICE_HISTORY
+            .with(|service| service.borrow().iter().map(|(_, change)| change).collect());
+        let adjustments: Vec<PendingAdjustment> = PENDING_ADJUSTMENTS.with(|service| {
+            service
+                .borrow()
+                .iter()
+                .map(|(_, adjustment)| adjustment)
+                .collect()
+        });
+
+        let max_id = timber.len() as u64;
+        let mut new_ids: HashMap<u64, u64> = timber
+            .iter()
+            .zip(1..)
+            .map(|(timber, new_id)| (timber.id, new_id))
+            .collect();
+        let mut next_id = [
+            max_id,
+            SALES_STORAGE.with(|service| service.borrow().last_key_value().map_or(0, |(id, _)| id)),
+            DELETED_SALES.with(|service| service.borrow().last_key_value().map_or(0, |(id, _)| id)),
+            ORDER_STORAGE.with(|service| service.borrow().last_key_value().map_or(0, |(id, _)| id)),
+            PRICE_HISTORY.with(|service| service.borrow().last_key_value().map_or(0, |(id, _)| id)),
+            RESERVATIONS.with(|service| service.borrow().last_key_value().map_or(0, |(id, _)| id)),
+            DEFAULT_PRICE_HISTORY
+                .with(|service| service.borrow().last_key_value().map_or(0, |(id, _)| id)),
+            PENDING_ADJUSTMENTS
+                .with(|service| service.borrow().last_key_value().map_or(0, |(id, _)| id)),
+        ]
+        .into_iter()
+        .max()
+        .unwrap_or(0)
+            + 1;
+        let dangling: BTreeSet<u64> = sales
+            .iter()
+            .chain(&deleted)
+            .map(|sales| sales.timber_id)
+            .chain(movements.iter().map(|((timber_id, _), _)| *timber_id))
+            .chain(reservations.iter().map(|reservation| reservation.timber_id))
+            .chain(price_changes.iter().map(|change| change.timber_id))
+            .chain(adjustments.iter().map(|adjustment| adjustment.timber_id))
+            .filter(|timber_id| !new_ids.contains_key(timber_id))
+            .collect();
+        for timber_id in dangling {
+            new_ids.insert(timber_id, next_id);
+            next_id += 1;
+        }
+        ID_COUNTER
+            .with(|counter| counter.borrow_mut().set(next_id))
+            .map_err(|_| "cannot reset id counter".to_string())?;
+
+        TIMBER_STORAGE.with(|service| {
+            let mut service = service.borrow_mut();
+            for timber in &timber {
+                service.remove(&timber.id);
+                TIMBER_TENANTS.with(|index| index.borrow_mut().remove(&timber.id));
+            }
+            for mut timber in timber {
+                timber.id = new_ids[&timber.id];
+                if let Some(sku) = &timber.sku {
+                    let key = sku_key(record_tenant(&timber.tenant), sku);
+                    SKU_INDEX.with(|index| index.borrow_mut().insert(key, timber.id));
+                }
+                TIMBER_TENANTS.with(|index| {
+                    index
+                        .borrow_mut()
+                        .insert(timber.id, indexed_tenant(&timber.tenant))
+                });
+                service.insert(timber.id, timber);
+            }
+        });
+        for mut sales in sales {
+            sales.timber_id = new_ids[&sales.timber_id];
+            do_insert_sales(&sales);
+        }
+        for mut sales in deleted {
+            sales.timber_id = new_ids[&sales.timber_id];
+            DELETED_SALES.with(|service| service.borrow_mut().insert(sales.id, sales));
+        }
+        STOCK_MOVEMENTS.with(|service| {
+            let mut service = service.borrow_mut();
+            for (key, _) in &movements {
+                service.remove(key);
+            }
+            for ((timber_id, number), mut movement) in movements {
+                movement.timber_id = new_ids[&timber_id];
+                service.insert((movement.timber_id, number), movement);
+            }
+        });
+        for mut reservation in reservations {
+            reservation.timber_id = new_ids[&reservation.timber_id];
+            RESERVATIONS.with(|service| service.borrow_mut().insert(reservation.id, reservation));
+        }
+        for mut change in price_changes {
+            change.timber_id = new_ids[&change.timber_id];
+            DEFAULT_PRICE_HISTORY.with(|service| service.borrow_mut().insert(change.id, change));
+        }
+        for mut adjustment in adjustments {
+            adjustment.timber_id = new_ids[&adjustment.timber_id];
+            PENDING_ADJUSTMENTS
+                .with(|service| service.borrow_mut().insert(adjustment.id, adjustment));
+        }
+        Ok(max_id)
+    })
+}
+
+//function to get the revenue, the sum of price * quantity, of the sales created between
+//start_ns and end_ns. deleted sales sit in the recycle bin and are not counted, reversals
+//created in the range count negatively
+#[ic_cdk::query]
+fn revenue_in_range(start_ns: u64, end_ns: u64) -> Result<u64, String> {
+    if start_ns > end_ns {
+        return Err("start_ns must not be after end_ns".to_string());
+    }
+    let sales: Vec<Sales> = partition_sales()
+        .into_iter()
+        .filter(|sales| sales.created_at >= start_ns && sales.created_at <= end_ns)
+        .collect();
+    sales_total(&sales).map_err(|_| "revenue overflows".to_string())
+}
+
+//function to set how much the update methods log, see LogLevel. owner only
+#[ic_cdk::update]
+fn set_log_level(level: LogLevel) -> Result<(), String> {
+    logged("set_log_level", format!("level={:?}", level), || {
+        ensure_owner()?;
+        set_setting(&LOG_LEVEL, level.to_setting())
+    })
+}
+
+//function to get how much the update methods log
+#[ic_cdk::query]
+fn get_log_level() -> LogLevel {
+    LogLevel::from_setting(get_setting(&LOG_LEVEL))
+}
+
+//function to get the lowest priced sale across the timber of a type and size, the oldest
+//one when several sold at that price. reversals and the sales they reverse don't count
+#[ic_cdk::query]
+fn cheapest_sale_for(timber_type: String, timber_size: String) -> Result<Sales, String> {
+    let timber_ids: HashSet<u64> =
+        get_timber_by_type_and_size(timber_type.clone(), timber_size.clone())?
+            .iter()
+            .map(|timber| timber.id)
+            .collect();
+    partition_sales()
+        .into_iter()
+        .filter(|sales| timber_ids.contains(&sales.timber_id) && is_standing_sale(sales))
+        .min_by_key(|sales| (sales.price, sales.id))
+        .ok_or_else(|| format!("no sales found for {} {}", timber_type, timber_size))
+}
+
+//function to hold stock of several timber for ttl_ns, at most MAX_RESERVATION_TTL_NS. every
+//item is checked for available stock before any reservation is created, so the whole hold is
+//rejected if one item fails. held stock can only be sold by the holder until the reservation
+//expires or is released. owner or authorized only
+#[ic_cdk::update]
+fn batch_reserve(items: Vec<(u64, u64)>, ttl_ns: u64) -> Result<Vec<Reservation>, String> {
+    logged(
+        "batch_reserve",
+        format!("items={} ttl_ns={}", items.len(), ttl_ns),
+        || {
+            check_rate_limit()?;
+            ensure_authorized()?;
+            if items.is_empty() {
+                return Err("a reservation must have at least one item".to_string());
+            }
+            if items.len() > MAX_ORDER_ITEMS {
+                return Err(format!(
+                    "a reservation can have at most {} items",
+                    MAX_ORDER_ITEMS
+                ));
+            }
+            if ttl_ns == 0 {
+                return Err("ttl_ns must be greater than 0".to_string());
+            }
+            if ttl_ns > MAX_RESERVATION_TTL_NS {
+                return Err(format!(
+                    "ttl_ns can be at most {} ns",
+                    MAX_RESERVATION_TTL_NS
+                ));
+            }
+            if items.iter().any(|(_, quantity)| *quantity == 0) {
+                return Err("reserved quantity must be greater than 0".to_string());
+            }
+            let now = time();
+            do_prune_reservations(now);
+            let payloads: Vec<SalesPayload> = items
+                .iter()
+                .map(|(timber_id, quantity)| SalesPayload {
+                    timber_id: *timber_id,
+                    quantity: *quantity,
+                    ..Default::default()
+                })
+                .collect();
+            //the caller's own holds count too, so stock can't be held twice
+            check_available(&payloads, true)?;
+
+            //all ids are taken up front so a counter failure can't leave a partial hold
+            let ids = items
+                .iter()
+                .map(|_| generate_unique_id())
+                .collect::<Result<Vec<u64>, String>>()?;
+            let reservations: Vec<Reservation> = ids
+                .into_iter()
+                .zip(items)
+                .map(|(id, (timber_id, quantity))| Reservation {
+                    id,
+                    timber_id,
+                    quantity,
+                    created_at: now,
+                    expires_at: now.saturating_add(ttl_ns),
+                    holder: Some(caller()),
+                })
+                .collect();
+            RESERVATIONS.with(|service| {
+                let mut service = service.borrow_mut();
+                for reservation in &reservations {
+                    service.insert(reservation.id, reservation.clone());
+                }
+            });
+            Ok(reservations)
+        },
+    )
+}
+
+//function to release a reservation before it expires, making its stock available again.
+//only the holder or the owner can release it
+#[ic_cdk::update]
+fn release_reservation(id: u64) -> Result<Reservation, String> {
+    logged("release_reservation", format!("id={}", id), || {
+        check_rate_limit()?;
+        match RESERVATIONS.with(|service| service.borrow().get(&id)) {
+            Some(reservation) => {
+                if record_tenant(&reservation.holder) != caller() && !is_owner() {
+                    return Err(
+                        "only the holder or the owner can release a reservation".to_string()
+                    );
+                }
+                RESERVATIONS.with(|service| service.borrow_mut().remove(&id));
+                Ok(reservation)
+            }
+            None => Err(format!(
+                "couldn't release a reservation with id={}. reservation not found",
+                id
+            )),
+        }
+    })
+}
+
+//function to list a page of sales, each paired with its line total as it counts towards
+//revenue, negative for a reversal, see net_line_total. a line total too large for an i64 is
+//given as i64::MAX, or i64::MIN for a reversal. pages are taken in id order
+#[ic_cdk::query]
+fn list_sales_with_totals(offset: u64, limit: u64) -> Vec<(Sales, i64)> {
+    partition_sales()
+        .into_iter()
+        .skip(offset as usize)
+        .take(limit.min(MAX_PAGE_SIZE) as usize)
+        .map(|sales| {
+            let total = net_line_total(&sales)
+                .and_then(|total| i64::try_from(total).ok())
+                .unwrap_or(if is_reversal(&sales) {
+                    i64::MIN
+                } else {
+                    i64::MAX
+                });
+            (sales, total)
+        })
+        .collect()
+}
+
+//function to get the timber in stock that hasn't sold since since_ns, candidates for clearance.
+//a timber whose sales since then have all been reversed hasn't sold
+#[ic_cdk::query]
+fn stale_inventory(since_ns: u64) -> Vec<Timber> {
+    let mut sold: HashMap<u64, i128> = HashMap::new();
+    for sales in partition_sales()
+        .iter()
+        .filter(|sales| sales.created_at >= since_ns)
+    {
+        *sold.entry(sales.timber_id).or_default() += net_quantity(sales);
+    }
+    let recently_sold: HashSet<u64> = sold
+        .into_iter()
+        .filter(|(_, quantity)| *quantity > 0)
+        .map(|(timber_id, _)| timber_id)
+        .collect();
+    partition_timber()
+        .into_iter()
+        .filter(|timber| timber.quantity > 0 && !recently_sold.contains(&timber.id))
+        .collect()
+}
+
+//function to get the timber and sales created or updated at or after timestamp_ns, for
+//clients syncing a local copy. records changed right at the boundary may be sent twice.
+//deletions are not included
+#[ic_cdk::query]
+fn changes_since(timestamp_ns: u64) -> ChangeSet {
+    let changed = |created_at: u64, updated_at: Option<u64>| {
+        created_at >= timestamp_ns
+            || updated_at.is_some_and(|updated_at| updated_at >= timestamp_ns)
+    };
+    ChangeSet {
+        timber: partition_timber()
+            .into_iter()
+            .filter(|timber| changed(timber.created_at, timber.updated_at))
+            .collect(),
+        sales: partition_sales()
+            .into_iter()
+            .filter(|sales| changed(sales.created_at, sales.updated_at))
+            .collect(),
+        server_time: time(),
+    }
+}
+
+//function to turn on or off rejecting sales priced below the unit cost of their timber.
+//off by default, turn it off for a while to allow clearance sales. owner only
+#[ic_cdk::update]
+fn set_block_below_cost(enabled: bool) -> Result<(), String> {
+    logged(
+        "set_block_below_cost",
+        format!("enabled={}", enabled),
+        || {
+            ensure_owner()?;
+            set_setting(&BLOCK_BELOW_COST, enabled as u64)
+        },
+    )
+}
+
+//function to get a timber together with its available stock, the quantity held by
+//reservations and its latest sales, leaving out reversals and the sales they reverse
+#[ic_cdk::query]
+fn timber_detail(id: u64) -> Result<TimberDetail, String> {
+    let timber = get_timber(id)?;
+    let active_reservations = reserved_quantity(id);
+    let mut recent_sales: Vec<Sales> = _get_sales_by_timber_id(&id)
+        .into_iter()
+        .filter(is_standing_sale)
+        .collect();
+    recent_sales.sort_by_key(|sales| std::cmp::Reverse((sales.created_at, sales.id)));
+    recent_sales.truncate(RECENT_SALES_LIMIT);
+    Ok(TimberDetail {
+        available: timber.quantity.saturating_sub(active_reservations),
+        active_reservations,
+        recent_sales,
+        timber,
+    })
+}
+
+//function to get the owner of the canister, the anonymous principal if none has been set
+#[ic_cdk::query]
+fn get_owner() -> Principal {
+    owner()
+}
+
+//function to list the principals the owner has authorized, sorted
+#[ic_cdk::query]
+fn list_authorized_principals() -> Vec<Principal> {
+    AUTHORIZED_PRINCIPALS.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .map(|(principal, _)| principal.0)
+            .collect()
+    })
+}
+
+//function to authorize a principal to run the register. owner only
+#[ic_cdk::update]
+fn add_authorized_principal(principal: Principal) -> Result<(), String> {
+    logged(
+        "add_authorized_principal",
+        format!("principal={}", principal),
+        || {
+            ensure_owner()?;
+            if principal == Principal::anonymous() {
+                return Err("the anonymous principal can't be authorized".to_string());
+            }
+            AUTHORIZED_PRINCIPALS.with(|service| {
+                service
+                    .borrow_mut()
+                    .insert(StorablePrincipal(principal), ())
+            });
+            Ok(())
+        },
+    )
+}
+
+//function to take away the authorization of a principal. owner only
+#[ic_cdk::update]
+fn remove_authorized_principal(principal: Principal) -> Result<(), String> {
+    logged(
+        "remove_authorized_principal",
+        format!("principal={}", principal),
+        || {
+            ensure_owner()?;
+            match AUTHORIZED_PRINCIPALS
+                .with(|service| service.borrow_mut().remove(&StorablePrincipal(principal)))
+            {
+                Some(()) => Ok(()),
+                None => Err(format!("principal {} is not authorized", principal)),
+            }
+        },
+    )
+}
+
+//function to undo the most recent sale in the owner's yard, where staff work, as
+//delete_sales would. the sales goes to the recycle bin and its quantity back into stock.
+//owner or authorized only
+#[ic_cdk::update]
+fn revert_last_sale() -> Result<Sales, String> {
+    logged("revert_last_sale", String::new(), || {
+        check_rate_limit()?;
+        ensure_authorized()?;
+        let sales = partition_sales()
+            .into_iter()
+            .max_by_key(|sales| (sales.created_at, sales.id))
+            .ok_or_else(|| "there are no sales to revert".to_string())?;
+        check_not_reversed(&sales)?;
+        check_delete_grace(&sales)?;
+        do_delete_sales(&sales, "sale deleted");
+        Ok(sales)
+    })
+}
+
+//function to set which of the optional fields must be given when adding or updating
+//records. nothing is required by default. owner only
+#[ic_cdk::update]
+fn set_field_policy(policy: FieldPolicy) -> Result<(), String> {
+    logged("set_field_policy", format!("policy={:?}", policy), || {
+        ensure_owner()?;
+        do_set_field_policy(policy)
+    })
+}
+
+//function to get which of the optional fields must be given
+#[ic_cdk::query]
+fn get_field_policy() -> FieldPolicy {
+    field_policy()
+}
+
+//function to get the units of a timber sold per day over the last window_ns, rounded to the
+//nearest whole unit. a window shorter than a day is scaled up to a daily rate
+#[ic_cdk::query]
+fn sales_velocity(timber_id: u64, window_ns: u64) -> Result<u64, String> {
+    if window_ns == 0 {
+        return Err("window_ns must be greater than 0".to_string());
+    }
+    get_timber(timber_id)?;
+    Ok(daily_velocity(timber_id, window_ns))
+}
+
+//function to set how many days a supplier takes to deliver. owner only
+#[ic_cdk::update]
+fn set_lead_time_days(days: u64) -> Result<(), String> {
+    logged("set_lead_time_days", format!("days={}", days), || {
+        ensure_owner()?;
+        set_setting(&LEAD_TIME_DAYS, days)
+    })
+}
+
+//function to get how many days a supplier takes to deliver
+#[ic_cdk::query]
+fn get_lead_time_days() -> u64 {
+    get_setting(&LEAD_TIME_DAYS)
+}
+
+//function to list what to order for every timber at or below its reorder point, enough to
+//cover the lead time at the last 30 days' sales velocity, in id order. the need is rounded
+//up only once it is worked out for the whole lead time, so slow sellers aren't rounded to 0
+#[ic_cdk::query]
+fn restock_suggestions() -> Vec<RestockSuggestion> {
+    let lead_time_days = get_setting(&LEAD_TIME_DAYS);
+    partition_timber()
+        .into_iter()
+        .filter(is_low_stock)
+        .map(|timber| {
+            let needed = apply_rounding(
+                units_sold_within(timber.id, RESTOCK_VELOCITY_WINDOW_NS)
+                    .saturating_mul(lead_time_days as u128 * DAY_NS as u128),
+                RESTOCK_VELOCITY_WINDOW_NS as u128,
+                RoundingMode::Ceil,
+            )
+            .min(u64::MAX as u128) as u64;
+            RestockSuggestion {
+                timber_id: timber.id,
+                current_quantity: timber.quantity,
+                reorder_point: timber.reorder_point.unwrap_or(0),
+                suggested_order_quantity: needed.saturating_sub(timber.quantity),
+            }
+        })
+        .collect()
+}
+
+//function to find likely double entered sales, pairs of sales with the same timber, quantity
+//and price created within window_ns of each other. each pair is (lower id, higher id) and
+//the pairs are sorted. reversals copy the sales they reverse, so they are left out. nothing
+//is deleted, the extras are left for staff to review
+#[ic_cdk::query]
+fn find_duplicate_sales(window_ns: u64) -> Vec<(u64, u64)> {
+    let mut sales: Vec<Sales> = partition_sales()
+        .into_iter()
+        .filter(|sales| !is_reversal(sales))
+        .collect();
+    sales.sort_by_key(|sales| {
+        (
+            sales.timber_id,
+            sales.quantity,
+            sales.price,
+            sales.created_at,
+            sales.id,
+        )
+    });
+    let mut pairs = Vec::new();
+    for (i, first) in sales.iter().enumerate() {
+        for second in &sales[i + 1..] {
+            let same = (first.timber_id, first.quantity, first.price)
+                == (second.timber_id, second.quantity, second.price);
+            if !same || second.created_at - first.created_at > window_ns {
+                break;
+            }
+            pairs.push((first.id.min(second.id), first.id.max(second.id)));
+        }
+    }
+    pairs.sort();
+    pairs
+}
+
+//function to set the canister whose low_stock_alert(timber_id, quantity) method is called
+//when a timber drops to or below its reorder point, none to stop the alerts. owner only
+#[ic_cdk::update]
+fn set_notify_canister(canister: Option<Principal>) -> Result<(), String> {
+    logged(
+        "set_notify_canister",
+        format!("canister={:?}", canister),
+        || {
+            ensure_owner()?;
+            do_set_notify_canister(canister)
+        },
+    )
+}
+
+//function to get the canister told about low stock
+#[ic_cdk::query]
+fn get_notify_canister() -> Option<Principal> {
+    let canister = NOTIFY_CANISTER.with(|cell| cell.borrow().get().0);
+    (canister != Principal::anonymous()).then_some(canister)
+}
+
+//function to set the volume pricing tiers of a timber, an empty list removes them. tiers
+//must be sorted by min_quantity with no two starting at the same quantity
+#[ic_cdk::update]
+fn set_pricing_tiers(timber_id: u64, tiers: Vec<PricingTier>) -> Result<Timber, String> {
+    logged(
+        "set_pricing_tiers",
+        format!("timber_id={} tiers={}", timber_id, tiers.len()),
+        || {
+            check_rate_limit()?;
+            let mut timber = get_timber(timber_id)?;
+            if tiers.len() > MAX_PRICING_TIERS {
+                return Err(format!(
+                    "a timber can have at most {} pricing tiers",
+                    MAX_PRICING_TIERS
+                ));
+            }
+            if tiers.iter().any(|tier| tier.min_quantity == 0) {
+                return Err("min_quantity must be greater than 0".to_string());
+            }
+            if tiers
+                .windows(2)
+                .any(|pair| pair[0].min_quantity >= pair[1].min_quantity)
+            {
+                return Err(
+                    "pricing tiers must be sorted by min_quantity without overlaps".to_string(),
+                );
+            }
+            timber.pricing_tiers = (!tiers.is_empty()).then_some(tiers);
+            timber.updated_at = Some(time());
+            do_insert_timber(&timber)?;
+            Ok(timber)
+        },
+    )
+}
+
+//function to get the unit price of a timber for a quantity, from the tier with the highest
+//min_quantity the quantity reaches
+#[ic_cdk::query]
+fn get_price_for_quantity(timber_id: u64, quantity: u64) -> Result<u64, String> {
+    let timber = get_timber(timber_id)?;
+    tier_price(&timber, quantity)
+        .ok_or_else(|| format!("no pricing tier applies to quantity {}", quantity))
+}
+
+//function to report whether the canister is healthy, with its record counts across every
+//yard. records that fail to decode make ok false rather than the call trap
+#[ic_cdk::query]
+fn health() -> HealthStatus {
+    let timber_ok = !any_timber(|timber| timber.decode_error.is_some());
+    let sales_ok = SALES_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .all(|(_, sales)| sales.decode_error.is_none())
+    });
+    HealthStatus {
+        ok: timber_ok && sales_ok,
+        timber_count: timber_count(),
+        sales_count: sales_count(),
+        id_counter: ID_COUNTER.with(|counter| *counter.borrow().get()),
+        time: time(),
+    }
+}
+
+//function to move every timber of type old over to type new, returning how many were
+//changed. the timber types are shared, so this changes the timber of every yard. owner only
+#[ic_cdk::update]
+fn relabel_timber_type(old: String, new: String) -> Result<u64, String> {
+    logged(
+        "relabel_timber_type",
+        format!("old={} new={}", old, new),
+        || {
+            ensure_owner()?;
+            if old == new {
+                return Err("old and new timber types must differ".to_string());
+            }
+            validate_timber_type(&new)?;
+            let matching: Vec<Timber> = TIMBER_STORAGE.with(|service| {
+                service
+                    .borrow()
+                    .iter()
+                    .filter(|(_, timber)| {
+                        timber.decode_error.is_none() && timber.timber_type == old
+                    })
+                    .map(|(_, timber)| timber)
+                    .collect()
+            });
+            let now = time();
+            let relabelled: Vec<Timber> = matching
+                .into_iter()
+                .map(|timber| Timber {
+                    timber_type: new.clone(),
+                    updated_at: Some(now),
+                    ..timber
+                })
+                .collect();
+            //a longer type name can push a record past its size, so none are written then
+            for timber in &relabelled {
+                check_timber_size(timber)?;
+            }
+            for timber in &relabelled {
+                do_insert_timber(timber)?;
+            }
+            Ok(relabelled.len() as u64)
+        },
+    )
+}
+
+//function to candid encode every timber, sales and order into one blob for backups. records
+//that fail to decode are left out. the blob has to fit in a single reply. owner only
+#[ic_cdk::query]
+fn export_snapshot() -> Result<Vec<u8>, String> {
+    ensure_owner()?;
+    let snapshot = Snapshot {
+        timber: TIMBER_STORAGE.with(|service| {
+            service
+                .borrow()
+                .iter()
+                .map(|(_, timber)| timber)
+                .filter(|timber| timber.decode_error.is_none())
+                .collect()
+        }),
+        sales: SALES_STORAGE.with(|service| {
+            service
+                .borrow()
+                .iter()
+                .map(|(_, sales)| sales)
+                .filter(|sales| sales.decode_error.is_none())
+                .collect()
+        }),
+        orders: Some(ORDER_STORAGE.with(|service| {
+            service
+                .borrow()
+                .iter()
+                .map(|(_, order)| order)
+                .filter(|order| order.decode_error.is_none())
+                .collect()
+        })),
+    };
+    Encode!(&snapshot).map_err(|e| format!("cannot encode the snapshot: {}", e))
+}
+
+//function to load a blob from export_snapshot, returning how many timber and sales were
+//imported. with merge the records are added to the current ones, and records whose id is
+//taken get a fresh id with the records tied to them pointed at it. without merge the
+//snapshot replaces the timber, sales and orders, and since it holds nothing else the records
+//tied to them go too: the sku index, stock movements, reservations, pending adjustments,
+//recycle bin and price history. a sales' order_id is taken from the orders in the snapshot
+//only, so sales of an order the snapshot doesn't hold are left without one. snapshots with
+//corrupted records, or that merge sales of timber they don't hold, are rejected. owner only
+#[ic_cdk::update]
+fn import_snapshot(data: Vec<u8>, merge: bool) -> Result<(u64, u64), String> {
+    logged(
+        "import_snapshot",
+        format!("bytes={} merge={}", data.len(), merge),
+        || {
+            ensure_owner()?;
+            let Snapshot {
+                timber,
+                sales,
+                orders,
+            } = Decode!(&data, Snapshot)
+                .map_err(|e| format!("cannot decode the snapshot: {}", e))?;
+            let orders = orders.unwrap_or_default();
+            let (timber_base, sales_base) = if merge {
+                (timber_count(), sales_count())
+            } else {
+                (0, 0)
+            };
+            check_record_limit(
+                timber_base,
+                timber.len() as u64,
+                get_setting(&MAX_TIMBER_RECORDS),
+            )?;
+            check_record_limit(
+                sales_base,
+                sales.len() as u64,
+                get_setting(&MAX_SALES_RECORDS),
+            )?;
+            for timestamp in timber
+                .iter()
+                .flat_map(|timber| [Some(timber.created_at), timber.updated_at])
+                .chain(
+                    sales
+                        .iter()
+                        .flat_map(|sales| [Some(sales.created_at), sales.updated_at]),
+                )
+                .flatten()
+            {
+                check_not_future(timestamp)?;
+            }
+            for timber in &timber {
+                check_timber_size(timber)?;
+            }
+            //a snapshot holds what export_snapshot could decode, anything else has been edited
+            if let Some(e) = timber
+                .iter()
+                .filter_map(|timber| timber.decode_error.as_ref())
+                .chain(sales.iter().filter_map(|sales| sales.decode_error.as_ref()))
+                .chain(
+                    orders
+                        .iter()
+                        .filter_map(|order| order.decode_error.as_ref()),
+                )
+                .next()
+            {
+                return Err(format!("the snapshot has a corrupted record: {}", e));
+            }
+            //merged sales are pointed at the new ids of their timber, which only the timber in
+            //the snapshot have. any other timber id would name an unrelated timber here
+            if merge {
+                let timber_ids: HashSet<u64> = timber.iter().map(|timber| timber.id).collect();
+                if let Some(sales) = sales
+                    .iter()
+                    .find(|sales| !timber_ids.contains(&sales.timber_id))
+                {
+                    return Err(format!(
+                        "sales id={} is for timber id={}, which is not in the snapshot",
+                        sales.id, sales.timber_id
+                    ));
+                }
+            }
+            let mut skus = HashSet::new();
+            for timber in &timber {
+                let Some(sku) = &timber.sku else {
+                    continue;
+                };
+                let tenant = record_tenant(&timber.tenant);
+                let taken = merge
+                    && SKU_INDEX.with(|index| index.borrow().contains_key(&sku_key(tenant, sku)));
+                if taken || !skus.insert((tenant, sku)) {
+                    return Err(format!("SKU {} is used more than once", sku));
+                }
+            }
+
+            //the counter is moved past the imported ids first, so fresh ids can't collide with them
+            let highest_id = timber
+                .iter()
+                .map(|timber| timber.id)
+                .chain(sales.iter().map(|sales| sales.id))
+                .chain(orders.iter().map(|order| order.id))
+                .max()
+                .unwrap_or(0);
+            ID_COUNTER
+                .with(|counter| {
+                    let next_id = (*counter.borrow().get()).max(highest_id.saturating_add(1));
+                    counter.borrow_mut().set(next_id)
+                })
+                .map_err(|_| "cannot increment id counter".to_string())?;
+            if !merge {
+                TIMBER_STORAGE.with(|service| clear_map(&mut service.borrow_mut()));
+                SALES_STORAGE.with(|service| clear_map(&mut service.borrow_mut()));
+                TIMBER_TENANTS.with(|index| clear_map(&mut index.borrow_mut()));
+                SALES_TENANTS.with(|index| clear_map(&mut index.borrow_mut()));
+                SKU_INDEX.with(|index| clear_map(&mut index.borrow_mut()));
+                STOCK_MOVEMENTS.with(|service| clear_map(&mut service.borrow_mut()));
+                RESERVATIONS.with(|service| clear_map(&mut service.borrow_mut()));
+                DELETED_SALES.with(|service| clear_map(&mut service.borrow_mut()));
+                ORDER_STORAGE.with(|service| clear_map(&mut service.borrow_mut()));
+                SALE_ORDERS.with(|index| clear_map(&mut index.borrow_mut()));
+                PRICE_HISTORY.with(|service| clear_map(&mut service.borrow_mut()));
+                DEFAULT_PRICE_HISTORY.with(|service| clear_map(&mut service.borrow_mut()));
+                PENDING_ADJUSTMENTS.with(|service| clear_map(&mut service.borrow_mut()));
+                REVERSED_SALES.with(|index| clear_map(&mut index.borrow_mut()));
+            }
+
+            let mut new_ids: HashMap<u64, u64> = HashMap::new();
+            let (timber_imported, sales_imported) = (timber.len() as u64, sales.len() as u64);
+            let imported_sales_ids: HashSet<u64> = sales.iter().map(|sales| sales.id).collect();
+            for mut timber in timber {
+                if TIMBER_STORAGE.with(|service| service.borrow().contains_key(&timber.id)) {
+                    let id = generate_unique_id()?;
+                    new_ids.insert(timber.id, id);
+                    timber.id = id;
+                }
+                do_insert_timber(&timber)?;
+                if let Some(sku) = &timber.sku {
+                    let key = sku_key(record_tenant(&timber.tenant), sku);
+                    SKU_INDEX.with(|index| index.borrow_mut().insert(key, timber.id));
+                }
+                do_record_movement(timber.id, to_delta(timber.quantity), "import");
+            }
+            //the sales ids are settled before any is written, so a reversal can be pointed at
+            //the new id of the sales it reverses
+            let mut new_sales_ids: HashMap<u64, u64> = HashMap::new();
+            for sales in &sales {
+                if SALES_STORAGE.with(|service| service.borrow().contains_key(&sales.id)) {
+                    new_sales_ids.insert(sales.id, generate_unique_id()?);
+                }
+            }
+            for mut sales in sales {
+                if let Some(id) = new_sales_ids.get(&sales.id) {
+                    sales.id = *id;
+                }
+                if let Some(id) = new_ids.get(&sales.timber_id) {
+                    sales.timber_id = *id;
+                }
+                if let Some(id) = sales.reversal_of.and_then(|id| new_sales_ids.get(&id)) {
+                    sales.reversal_of = Some(*id);
+                }
+                //set again below from the orders in the snapshot, under their new ids
+                sales.order_id = None;
+                do_insert_sales(&sales);
+                do_index_reversal(&sales);
+            }
+            //an order keeps only the sales the snapshot holds, a sale id from elsewhere could
+            //name an unrelated sales here
+            for mut order in orders {
+                if ORDER_STORAGE.with(|service| service.borrow().contains_key(&order.id)) {
+                    order.id = generate_unique_id()?;
+                }
+                order.sale_ids = order
+                    .sale_ids
+                    .iter()
+                    .filter(|id| imported_sales_ids.contains(id))
+                    .map(|id| *new_sales_ids.get(id).unwrap_or(id))
+                    .collect();
+                do_insert_order(&order);
+            }
+            Ok((timber_imported, sales_imported))
+        },
+    )
+}
+
+//function to get the timber sorted by stock value, quantity * unit_cost, for stocktaking.
+//timber without a unit cost counts as worth nothing and ties are sorted by id
+#[ic_cdk::query]
+fn timber_by_value(descending: bool) -> Vec<Timber> {
+    let mut timber = partition_timber();
+    if descending {
+        timber.sort_by_key(|timber| (std::cmp::Reverse(timber_value(timber)), timber.id));
+    } else {
+        timber.sort_by_key(|timber| (timber_value(timber), timber.id));
+    }
+    timber
+}
+
+//function to get how many times each method has been called, most called first. queries
+//can't change state, so only update methods are counted
+#[ic_cdk::query]
+fn query_stats() -> Vec<(String, u64)> {
+    let mut stats: Vec<(String, u64)> = METHOD_CALLS.with(|calls| {
+        calls
+            .borrow()
+            .iter()
+            .map(|(method, count)| (method.0, count))
+            .collect()
+    });
+    stats.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+    stats
+}
+
+//function to get every sales whose timber no longer exists in the caller's yard, in id order,
+//so they can be deleted or moved to other timber
+#[ic_cdk::query]
+fn orphaned_sales() -> Vec<Sales> {
+    let timber_ids: HashSet<u64> = partition_timber().iter().map(|timber| timber.id).collect();
+    partition_sales()
+        .into_iter()
+        .filter(|sales| !timber_ids.contains(&sales.timber_id))
+        .collect()
+}
+
+//function to write a scaled quantity of a timber with its decimal places, e.g. 125 as 1.25
+//for timber with 2 quantity decimals
+#[ic_cdk::query]
+fn format_timber_quantity(timber_id: u64, quantity: u64) -> Result<String, String> {
+    let timber = get_timber(timber_id)?;
+    Ok(format_quantity(
+        quantity,
+        timber.quantity_decimals.unwrap_or(0),
+    ))
+}
+
+//function to read a quantity of a timber written with its decimal places as the scaled
+//quantity to give to add_sales and the other methods, e.g. 1.25 as 125 at 2 decimals
+#[ic_cdk::query]
+fn parse_timber_quantity(timber_id: u64, text: String) -> Result<u64, String> {
+    let timber = get_timber(timber_id)?;
+    parse_quantity(text.trim(), timber.quantity_decimals.unwrap_or(0))
+}
+
+//function to get the revenue of the caller's sales by the utc weekday they were created on,
+//as (weekday, revenue) for every weekday from 0, sunday, to 6, saturday. reversals count
+//negatively on the day they were made. revenue saturates rather than overflowing
+#[ic_cdk::query]
+fn sales_by_weekday() -> Vec<(u8, u64)> {
+    let mut revenue = [0i128; 7];
+    for sales in partition_sales() {
+        let weekday = utc_weekday(sales.created_at) as usize;
+        let line_total = net_line_total(&sales).unwrap_or(u64::MAX as i128);
+        revenue[weekday] = revenue[weekday].saturating_add(line_total);
+    }
+    (0..)
+        .zip(revenue.map(|revenue| revenue.clamp(0, u64::MAX as i128) as u64))
+        .collect()
+}
+
+//function to get the timber whose stock value, quantity * unit_cost, is between min and max
+//inclusive, in id order. timber without a unit cost is worth 0
+#[ic_cdk::query]
+fn timber_in_value_range(min: u64, max: u64) -> Result<Vec<Timber>, String> {
+    if min > max {
+        return Err("min must not be greater than max".to_string());
+    }
+    Ok(partition_timber()
+        .into_iter()
+        .filter(|timber| (min..=max).contains(&timber_value(timber)))
+        .collect())
+}
+
+//function to get how long every timber in stock has been in the yard, as (timber id, age in
+//ns since it was added), oldest first
+#[ic_cdk::query]
+fn inventory_aging() -> Vec<(u64, u64)> {
+    let now = time();
+    let mut aging: Vec<(u64, u64)> = partition_timber()
+        .into_iter()
+        .filter(|timber| timber.quantity > 0)
+        .map(|timber| (timber.id, now.saturating_sub(timber.created_at)))
+        .collect();
+    aging.sort_by_key(|(id, age_ns)| (std::cmp::Reverse(*age_ns), *id));
+    aging
+}
+
+//function to get the ids of the timber in stock grouped by age, under 30 days, 30 to 90 days
+//and over 90 days, each oldest first
+#[ic_cdk::query]
+fn inventory_aging_buckets() -> AgingBuckets {
+    let mut buckets = AgingBuckets::default();
+    for (id, age_ns) in inventory_aging() {
+        let bucket = if age_ns < 30 * DAY_NS {
+            &mut buckets.under_30_days
+        } else if age_ns <= 90 * DAY_NS {
+            &mut buckets.from_30_to_90_days
+        } else {
+            &mut buckets.over_90_days
+        };
+        bucket.push(id);
+    }
+    buckets
+}
+
+//function to get the sales with a price between min and max inclusive, sorted by price and
+//then by id. reversals are corrections rather than sales, so they are left out
+#[ic_cdk::query]
+fn sales_in_price_range(min: u64, max: u64) -> Result<Vec<Sales>, String> {
+    if min > max {
+        return Err("min must not be greater than max".to_string());
+    }
+    let mut sales: Vec<Sales> = partition_sales()
+        .into_iter()
+        .filter(|sales| !is_reversal(sales) && (min..=max).contains(&sales.price))
+        .collect();
+    sales.sort_by_key(|sales| (sales.price, sales.id));
+    Ok(sales)
+}
+
+//function to bring records from before validation was tightened into their canonical form,
+//returning how many (timber, sales) were changed. timber types are trimmed and lowercased,
+//sizes canonicalized and the other text fields trimmed. records already canonical or that
+//fail to decode are left alone, so they keep their updated_at. owner only
+#[ic_cdk::update]
+fn normalize_existing_data() -> Result<(u64, u64), String> {
+    logged("normalize_existing_data", String::new(), || {
+        ensure_owner()?;
+        let now = time();
+        let timber: Vec<Timber> = TIMBER_STORAGE.with(|service| {
+            service
+                .borrow()
+                .iter()
+                .filter(|(_, timber)| timber.decode_error.is_none())
+                .filter_map(|(_, timber)| normalized_timber(&timber))
+                .collect()
+        });
+        for timber in &timber {
+            check_timber_size(timber)?;
+        }
+        for timber in &timber {
+            do_insert_timber(&Timber {
+                updated_at: Some(now),
+                ..timber.clone()
+            })?;
+        }
+        let sales: Vec<Sales> = SALES_STORAGE.with(|service| {
+            service
+                .borrow()
+                .iter()
+                .filter(|(_, sales)| sales.decode_error.is_none())
+                .filter_map(|(_, sales)| normalized_sales(&sales))
+                .collect()
+        });
+        for sales in &sales {
+            do_insert_sales(&Sales {
+                updated_at: Some(now),
+                ..sales.clone()
+            });
+        }
+        Ok((timber.len() as u64, sales.len() as u64))
+    })
+}
+
+//function to get the total quantity of all the caller's timber. summed as u128 and capped
+//at u64::MAX
+#[ic_cdk::query]
+fn total_units_in_stock() -> u64 {
+    let total: u128 = partition_timber()
+        .iter()
+        .map(|timber| timber.quantity as u128)
+        .sum();
+    total.min(u64::MAX as u128) as u64
+}
+
+//function to get the total quantity of the caller's timber for every size, sorted by width
+//and then height. summed as u128 and capped at u64::MAX
+#[ic_cdk::query]
+fn total_units_by_size() -> Vec<(String, u64)> {
+    let mut totals: HashMap<String, u128> = HashMap::new();
+    for timber in partition_timber() {
+        *totals.entry(timber.timber_size).or_default() += timber.quantity as u128;
+    }
+    let mut totals: Vec<(String, u64)> = totals
+        .into_iter()
+        .map(|(size, total)| (size, total.min(u64::MAX as u128) as u64))
+        .collect();
+    totals.sort_by_cached_key(|(size, _)| (size_dimensions(size), size.clone()));
+    totals
+}
+
+//function to search the caller's timber and sales for a term, ignoring case. timber matches
+//on its type, size or supplier and sales on its customer or id. an empty or overly long
+//term matches nothing rather than scanning for everything
+#[ic_cdk::query]
+fn global_search(term: String) -> SearchResults {
+    let term = term.trim().to_lowercase();
+    if term.is_empty() || term.len() > MAX_SEARCH_LENGTH {
+        return SearchResults::default();
+    }
+    let matches = |text: &str| text.to_lowercase().contains(&term);
+    SearchResults {
+        timber: partition_timber()
+            .into_iter()
+            .filter(|timber| {
+                matches(&timber.timber_type)
+                    || matches(&timber.timber_size)
+                    || timber.supplier.as_deref().is_some_and(matches)
+            })
+            .take(MAX_PAGE_SIZE as usize)
+            .collect(),
+        sales: partition_sales()
+            .into_iter()
+            .filter(|sales| {
+                sales.customer.as_deref().is_some_and(matches) || matches(&sales.id.to_string())
+            })
+            .take(MAX_PAGE_SIZE as usize)
+            .collect(),
+    }
+}
+
+//function to get every owner settable setting in one call, for a settings page
+#[ic_cdk::query]
+fn get_config() -> Config {
+    Config {
+        rate_limit: get_setting(&RATE_LIMIT),
+        rounding_mode: rounding_mode(),
+        min_price: get_setting(&MIN_PRICE),
+        max_price: get_setting(&MAX_PRICE),
+        block_below_cost: get_setting(&BLOCK_BELOW_COST) != 0,
+        abc_a_percent: get_setting(&ABC_A_PERCENT),
+        abc_b_percent: get_setting(&ABC_B_PERCENT),
+        log_level: get_log_level(),
+        field_policy: field_policy(),
+        notify_canister: get_notify_canister(),
+        lead_time_days: get_setting(&LEAD_TIME_DAYS),
+        delete_grace_ns: get_setting(&DELETE_GRACE_NS),
+        max_timber_records: get_setting(&MAX_TIMBER_RECORDS),
+        max_sales_records: get_setting(&MAX_SALES_RECORDS),
+        business_hours_start_ns: get_setting(&BUSINESS_HOURS_START_NS),
+        business_hours_end_ns: get_setting(&BUSINESS_HOURS_END_NS),
+    }
+}
+
+//function to write every owner settable setting at once, as read by get_config. every value
+//is checked before any is written, so an invalid one, named in the error, leaves all the
+//settings as they were. a write failing part way puts back the settings read beforehand.
+//owner only
+#[ic_cdk::update]
+fn apply_config(cfg: Config) -> Result<(), String> {
+    logged("apply_config", String::new(), || {
+        ensure_owner()?;
+        let field = |name: &'static str| move |e: String| format!("invalid {}: {}", name, e);
+        check_rate_limit_setting(cfg.rate_limit).map_err(field("rate_limit"))?;
+        check_price_limits_setting(cfg.min_price, cfg.max_price)
+            .map_err(field("min_price/max_price"))?;
+        check_abc_thresholds_setting(cfg.abc_a_percent, cfg.abc_b_percent)
+            .map_err(field("abc_a_percent/abc_b_percent"))?;
+        check_business_hours_setting(cfg.business_hours_start_ns, cfg.business_hours_end_ns)
+            .map_err(field("business_hours_start_ns/business_hours_end_ns"))?;
+
+        let previous = get_config();
+        do_write_config(cfg).inspect_err(|_| {
+            //the old values were stored before, so writing them back doesn't need new memory
+            let _ = do_write_config(previous);
+        })
+    })
+}
+
+//helper method to write every setting of a config, stopping at the first write that fails
+fn do_write_config(cfg: Config) -> Result<(), String> {
+    set_setting(&RATE_LIMIT, cfg.rate_limit)?;
+    set_setting(&ROUNDING_MODE, cfg.rounding_mode.to_setting())?;
+    set_setting(&MIN_PRICE, cfg.min_price)?;
+    set_setting(&MAX_PRICE, cfg.max_price)?;
+    set_setting(&BLOCK_BELOW_COST, cfg.block_below_cost as u64)?;
+    set_setting(&ABC_A_PERCENT, cfg.abc_a_percent)?;
+    set_setting(&ABC_B_PERCENT, cfg.abc_b_percent)?;
+    set_setting(&LOG_LEVEL, cfg.log_level.to_setting())?;
+    do_set_field_policy(cfg.field_policy)?;
+    do_set_notify_canister(cfg.notify_canister)?;
+    set_setting(&LEAD_TIME_DAYS, cfg.lead_time_days)?;
+    set_setting(&DELETE_GRACE_NS, cfg.delete_grace_ns)?;
+    set_setting(&MAX_TIMBER_RECORDS, cfg.max_timber_records)?;
+    set_setting(&MAX_SALES_RECORDS, cfg.max_sales_records)?;
+    set_setting(&BUSINESS_HOURS_START_NS, cfg.business_hours_start_ns)?;
+    set_setting(&BUSINESS_HOURS_END_NS, cfg.business_hours_end_ns)
+}
+
+//function to export every sales of the caller's yard as a json array in id order, each with
+//the type and size of its timber, for loading into analytics tools
+#[ic_cdk::query]
+fn export_sales_json() -> String {
+    let timber: HashMap<u64, Timber> = partition_timber()
+        .into_iter()
+        .map(|timber| (timber.id, timber))
+        .collect();
+    let sales: Vec<SalesExport> = partition_sales()
+        .into_iter()
+        .map(|sales| {
+            let timber = timber.get(&sales.timber_id);
+            SalesExport {
+                id: sales.id,
+                timber_id: sales.timber_id,
+                timber_type: timber.map(|timber| timber.timber_type.clone()),
+                timber_size: timber.map(|timber| timber.timber_size.clone()),
+                quantity: sales.quantity,
+                price: sales.price,
+                is_reversal: is_reversal(&sales),
+                customer: sales.customer,
+                created_at: sales.created_at,
+                updated_at: sales.updated_at,
+                reversal_of: sales.reversal_of,
+                order_id: sales.order_id,
+            }
+        })
+        .collect();
+    serde_json::to_string(&sales)
+        .unwrap_or_else(|e| ic_cdk::trap(&format!("cannot write the sales as json: {}", e)))
+}
+
+//function to check whether a timber exists in the caller's yard without returning it. only
+//the tenant index is read, the timber itself isn't decoded
+#[ic_cdk::query]
+fn timber_exists(id: u64) -> bool {
+    indexed_in_partition(&TIMBER_TENANTS, id)
+}
+
+//function to check whether a sales exists in the caller's yard without returning it. only
+//the tenant index is read, the sales itself isn't decoded
+#[ic_cdk::query]
+fn sales_exists(id: u64) -> bool {
+    indexed_in_partition(&SALES_TENANTS, id)
+}
+
+//function to ask for a change to the stock of a timber. the change is only applied once
+//another authorized principal approves it, see approve_adjustment
+#[ic_cdk::update]
+fn request_adjustment(
+    timber_id: u64,
+    delta: i64,
+    reason: String,
+) -> Result<PendingAdjustment, String> {
+    logged(
+        "request_adjustment",
+        format!("timber_id={} delta={}", timber_id, delta),
+        || {
+            check_rate_limit()?;
+            ensure_authorized()?;
+            if delta == 0 {
+                return Err("delta must not be zero".to_string());
+            }
+            let reason = check_change_reason(Some(reason), delta)?
+                .ok_or_else(|| "Adjustment requires a reason".to_string())?;
+            if _get_timber(&timber_id).is_none() {
+                return Err(format!("timber with id={} not found", timber_id));
+            }
+            let adjustment = PendingAdjustment {
+                id: generate_unique_id()?,
+                timber_id,
+                delta,
+                reason,
+                requested_by: caller(),
+                requested_at: time(),
+                yard: Some(yard()),
+            };
+            PENDING_ADJUSTMENTS.with(|service| {
+                service
+                    .borrow_mut()
+                    .insert(adjustment.id, adjustment.clone())
+            });
+            Ok(adjustment)
+        },
+    )
+}
+
+//function to apply a pending stock adjustment. the approver must be an authorized principal
+//other than the one who asked for it
+#[ic_cdk::update]
+fn approve_adjustment(id: u64) -> Result<Timber, String> {
+    logged("approve_adjustment", format!("id={}", id), || {
+        check_rate_limit()?;
+        ensure_authorized()?;
+        let adjustment = PENDING_ADJUSTMENTS
+            .with(|service| service.borrow().get(&id))
+            .ok_or_else(|| format!("pending adjustment with id={} not found", id))?;
+        if adjustment.requested_by == caller() {
+            return Err("Approver must differ from requester".to_string());
+        }
+        //the adjustment applies to the yard it was asked for in, which need not be the
+        //approver's
+        let yard = adjustment
+            .yard
+            .unwrap_or_else(|| yard_of(adjustment.requested_by));
+        let mut timber = _get_timber_by_id(&adjustment.timber_id)
+            .filter(|timber| record_tenant(&timber.tenant) == yard)
+            .ok_or_else(|| format!("timber with id={} not found", adjustment.timber_id))?;
+        let quantity = if adjustment.delta < 0 {
+            timber
+                .quantity
+                .checked_sub(adjustment.delta.unsigned_abs())
+                .ok_or_else(|| {
+                    format!(
+                        "adjustment would take the stock of timber with id={} below zero",
+                        timber.id
+                    )
+                })?
+        } else {
+            timber
+                .quantity
+                .checked_add(adjustment.delta.unsigned_abs())
+                .ok_or_else(|| "Quantity overflows".to_string())?
+        };
+        check_capacity(timber.quantity, quantity, timber.max_capacity)?;
+        let was_low = is_low_stock(&timber);
+        timber.quantity = quantity;
+        timber.updated_at = Some(time());
+        do_insert_timber(&timber)?;
+        //the prefix keeps the requester's reason from reading as a sale, see is_sale_movement
+        let reason = format!("adjustment: {}", adjustment.reason);
+        do_record_movement(timber.id, adjustment.delta, &reason);
+        PENDING_ADJUSTMENTS.with(|service| service.borrow_mut().remove(&id));
+        if !was_low {
+            do_notify_low_stock(&timber);
+        }
+        Ok(timber)
+    })
+}
+
+//function to drop a pending stock adjustment without applying it
+#[ic_cdk::update]
+fn reject_adjustment(id: u64) -> Result<PendingAdjustment, String> {
+    logged("reject_adjustment", format!("id={}", id), || {
+        check_rate_limit()?;
+        ensure_authorized()?;
+        PENDING_ADJUSTMENTS
+            .with(|service| service.borrow_mut().remove(&id))
+            .ok_or_else(|| format!("pending adjustment with id={} not found", id))
+    })
+}
+
+//function to list the stock adjustments waiting for approval, oldest first
+#[ic_cdk::query]
+fn list_pending_adjustments() -> Result<Vec<PendingAdjustment>, String> {
+    ensure_authorized()?;
+    Ok(PENDING_ADJUSTMENTS.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .map(|(_, adjustment)| adjustment)
+            .collect()
+    }))
+}
+
+//function to check the stock of every timber against what its stock movements and sales say
+//it should be, for yards that had sales before sales took stock out. the expected stock is
+//what came in according to the movement ledger less the net quantity sold, see
+//expected_stock. returns how many timber don't match. nothing is changed unless fix is set,
+//then each mismatch that isn't below zero gets the expected stock. owner only
+#[ic_cdk::update]
+fn reconcile_stock_from_sales(fix: bool) -> Result<u64, String> {
+    logged("reconcile_stock_from_sales", format!("fix={}", fix), || {
+        check_rate_limit()?;
+        ensure_owner()?;
+        let mut sales_by_timber: HashMap<u64, Vec<Sales>> = HashMap::new();
+        SALES_STORAGE.with(|service| {
+            for (_, sales) in service.borrow().iter() {
+                sales_by_timber
+                    .entry(sales.timber_id)
+                    .or_default()
+                    .push(sales);
+            }
+        });
+        let timber: Vec<Timber> = TIMBER_STORAGE.with(|service| {
+            service
+                .borrow()
+                .iter()
+                .map(|(_, timber)| timber)
+                .filter(|timber| timber.decode_error.is_none())
+                .collect()
+        });
+        let mut mismatched = 0;
+        for mut timber in timber {
+            let sales = sales_by_timber.remove(&timber.id).unwrap_or_default();
+            let Some(expected) = expected_stock(timber.id, &sales) else {
+                continue;
+            };
+            if expected == timber.quantity as i128 {
+                continue;
+            }
+            mismatched += 1;
+            if !fix {
+                continue;
+            }
+            if let Ok(quantity) = u64::try_from(expected) {
+                let delta = to_delta(quantity) - to_delta(timber.quantity);
+                timber.quantity = quantity;
+                timber.updated_at = Some(time());
+                do_insert_timber(&timber)?;
+                do_record_movement(timber.id, delta, "reconciled");
+            }
+        }
+        Ok(mismatched)
+    })
+}
+
+//function to remove the cancelled sales in the recycle bin created before timestamp_ns for
+//good, returning how many were removed. their price history and order links go with them,
+//and an order left without sales is removed. active sales and timber are left alone. the
+//cutoff must be at least MIN_PURGE_AGE_NS in the past so recent records can't be purged.
+//owner only
+#[ic_cdk::update]
+fn purge_cancelled_sales_before(timestamp_ns: u64) -> Result<u64, String> {
+    logged(
+        "purge_cancelled_sales_before",
+        format!("timestamp_ns={}", timestamp_ns),
+        || {
+            check_rate_limit()?;
+            ensure_owner()?;
+            if timestamp_ns > time().saturating_sub(MIN_PURGE_AGE_NS) {
+                return Err(format!(
+                    "the cutoff must be at least {} days in the past",
+                    MIN_PURGE_AGE_NS / DAY_NS
+                ));
+            }
+            let ids: HashSet<u64> = DELETED_SALES.with(|service| {
+                let mut service = service.borrow_mut();
+                let ids: HashSet<u64> = service
+                    .iter()
+                    .filter(|(_, sales)| sales.created_at < timestamp_ns)
+                    .map(|(id, _)| id)
+                    .collect();
+                for id in &ids {
+                    service.remove(id);
+                }
+                ids
+            });
+            do_forget_sales(&ids);
+            Ok(ids.len() as u64)
+        },
+    )
+}
+
+//helper method to get the next id from the shared id counter
+fn generate_unique_id() -> Result<u64, String> {
+    ID_COUNTER
+        .with(|counter| {
+            let current_value = *counter.borrow().get();
+            counter.borrow_mut().set(current_value + 1)
+        })
+        .map_err(|_| "cannot increment id counter".to_string())
+}
+
+//helper method to get the owner of the canister
+fn owner() -> Principal {
+    OWNER.with(|owner| owner.borrow().get().0)
+}
+
+//helper method to set the owner of the canister
+fn set_owner(principal: Principal) {
+    OWNER
+        .with(|owner| owner.borrow_mut().set(StorablePrincipal(principal)))
+        .expect("cannot set the owner");
+}
+
+//helper method to check whether the caller is the owner of the canister
+fn is_owner() -> bool {
+    let owner = owner();
+    owner != Principal::anonymous() && caller() == owner
+}
+
+//helper method to reject a call that isn't made by the owner
+fn ensure_owner() -> Result<(), String> {
+    if is_owner() {
+        Ok(())
+    } else {
+        Err("only the owner can perform this action".to_string())
+    }
+}
+
+//helper method to check whether the owner has authorized a principal
+fn is_authorized(principal: Principal) -> bool {
+    AUTHORIZED_PRINCIPALS
+        .with(|service| service.borrow().contains_key(&StorablePrincipal(principal)))
+}
+
+//helper method to reject a call that isn't made by the owner or an authorized principal
+fn ensure_authorized() -> Result<(), String> {
+    if is_authorized(caller()) || is_owner() {
+        Ok(())
+    } else {
+        Err("only the owner or an authorized principal can perform this action".to_string())
+    }
+}
+
+//helper method to record an update call from the caller and reject it once the caller
+//has made more calls in the last minute than the rate limit allows. the owner is exempt
+fn check_rate_limit() -> Result<(), String> {
+    let limit = get_setting(&RATE_LIMIT);
+    if limit == 0 || is_owner() {
+        return Ok(());
+    }
+    let now = time();
+    let window_start = now.saturating_sub(RATE_LIMIT_WINDOW_NS);
+    let caller = StorablePrincipal(caller());
+    CALL_LOGS.with(|service| {
+        let mut service = service.borrow_mut();
+        let mut log = service.get(&caller).unwrap_or_default();
+        log.timestamps.retain(|timestamp| *timestamp > window_start);
+        if log.timestamps.len() as u64 >= limit {
+            return Err("Rate limit exceeded".to_string());
+        }
+        if log.timestamps.is_empty() && service.len() >= MAX_TRACKED_CALLERS {
+            let idle: Vec<StorablePrincipal> = service
+                .iter()
+                .filter(|(_, log)| log.timestamps.iter().all(|t| *t <= window_start))
+                .map(|(principal, _)| principal)
+                .collect();
+            for principal in &idle {
+                service.remove(principal);
+            }
+        }
+        log.timestamps.push(now);
+        service.insert(caller, log);
+        Ok(())
+    })
+}
+
+//helper method to get the configured rounding mode
+fn rounding_mode() -> RoundingMode {
+    RoundingMode::from_setting(get_setting(&ROUNDING_MODE))
+}
+
+//helper method to divide numerator by denominator using the given rounding mode.
+//price calculations go through this so they all round the same way.
+//the denominator must not be zero
+fn apply_rounding(numerator: u128, denominator: u128, mode: RoundingMode) -> u128 {
+    let quotient = numerator / denominator;
+    let remainder = numerator % denominator;
+    let round_up = match mode {
+        RoundingMode::Floor => false,
+        RoundingMode::Ceil => remainder > 0,
+        RoundingMode::HalfUp => remainder >= denominator - remainder,
+    };
+    if round_up {
+        quotient + 1
+    } else {
+        quotient
+    }
+}
+
+//helper method to read a setting cell
+fn get_setting(setting: &'static std::thread::LocalKey<RefCell<SettingCell>>) -> u64 {
+    setting.with(|cell| *cell.borrow().get())
+}
+
+//helper method to write a setting cell
+fn set_setting(
+    setting: &'static std::thread::LocalKey<RefCell<SettingCell>>,
+    value: u64,
+) -> Result<(), String> {
+    setting
+        .with(|cell| cell.borrow_mut().set(value))
+        .map(|_| ())
+        .map_err(|_| "cannot update the setting".to_string())
+}
+
+//helper method to get the number of timber records
+fn timber_count() -> u64 {
+    TIMBER_STORAGE.with(|service| service.borrow().len())
+}
+
+//helper method to get the number of sales records
+fn sales_count() -> u64 {
+    SALES_STORAGE.with(|service| service.borrow().len())
+}
+
+//helper method to check that adding records keeps the count within its limit, 0 means unlimited
+fn check_record_limit(count: u64, adding: u64, limit: u64) -> Result<(), String> {
+    if limit != 0 && count.saturating_add(adding) > limit {
+        return Err("Record limit reached".to_string());
+    }
+    Ok(())
+}
+
+//helper method to check the type, size and supplier of a timber, returning the size in its
+//canonical form. empty values get their own message rather than failing as an invalid value
+fn validate_timber_fields(
+    timber_type: &str,
+    timber_size: &str,
+    supplier: &Option<String>,
+) -> Result<String, String> {
+    if timber_type.trim().is_empty() {
+        return Err("Timber type must not be empty".to_string());
+    }
+    if timber_size.trim().is_empty() {
+        return Err("Timber size must not be empty".to_string());
+    }
+    validate_timber_type(timber_type)?;
+    let timber_size = validate_timber_size(timber_size)?;
+    if matches!(supplier, Some(supplier) if supplier.trim().is_empty()) {
+        return Err("Supplier must not be empty".to_string());
+    }
+    Ok(timber_size)
+}
+
+//helper method to run the checks of add_timber on a payload, returning it with the unit
+//defaulted and the size, unit, location and sku normalized. shared with validate_timber_payload
+fn check_timber_payload(timber: TimberPayload) -> Result<TimberPayload, String> {
+    check_record_limit(timber_count(), 1, get_setting(&MAX_TIMBER_RECORDS))?;
+    let timber_size =
+        validate_timber_fields(&timber.timber_type, &timber.timber_size, &timber.supplier)?;
+    check_timber_policy(&timber.supplier, &timber.unit_cost)?;
+    let unit = validate_unit(timber.unit.unwrap_or_else(|| DEFAULT_UNIT.to_string()))?;
+    let location = timber.location.map(validate_location).transpose()?;
+    let sku = timber.sku.map(validate_sku).transpose()?;
+    check_bundle_size(timber.bundle_size)?;
+    check_quantity_decimals(timber.quantity_decimals)?;
+    check_capacity(0, timber.quantity, timber.max_capacity)?;
+    if let Some(price) = timber.default_unit_price {
+        check_price(price)?;
+    }
+    Ok(TimberPayload {
+        timber_size,
+        unit: Some(unit),
+        location,
+        sku,
+        ..timber
+    })
+}
+
+//helper method to run the checks of add_sales on a payload, returning it with the quantity
+//converted to the unit of the timber and the price filled in. shared with
+//validate_sales_payload
+fn check_sales_payload(sales: SalesPayload) -> Result<SalesPayload, String> {
+    let sales = &resolve_price(convert_sale_unit(sales)?)?;
+    check_record_limit(sales_count(), 1, get_setting(&MAX_SALES_RECORDS))?;
+    check_business_hours()?;
+    if matches!(&sales.customer, Some(customer) if customer.trim().is_empty()) {
+        return Err("Customer must not be empty".to_string());
+    }
+    if field_policy().require_customer && sales.customer.is_none() {
+        return Err("Customer is required".to_string());
+    }
+    check_occurred_at(std::slice::from_ref(sales))?;
+    check_price(sales.unit_price())?;
+    check_line_total(sales.quantity, sales.unit_price())?;
+    check_sale_increment(std::slice::from_ref(sales))?;
+    check_below_cost(std::slice::from_ref(sales))?;
+    check_stock(std::slice::from_ref(sales))?;
+    check_fifo(std::slice::from_ref(sales))?;
+    Ok(sales.clone())
+}
+
+//helper method to get which of the optional fields must be given
+fn field_policy() -> FieldPolicy {
+    FIELD_POLICY.with(|cell| *cell.borrow().get())
+}
+
+//helper method to check the reason given for a change of quantity by delta, returning it
+//trimmed. a blank reason counts as none, which the field policy can forbid
+fn check_change_reason(reason: Option<String>, delta: i64) -> Result<Option<String>, String> {
+    let reason = reason
+        .map(|reason| reason.trim().to_string())
+        .filter(|reason| !reason.is_empty());
+    if matches!(&reason, Some(reason) if reason.len() > MAX_REASON_LENGTH) {
+        return Err(format!(
+            "Reason must be at most {} bytes",
+            MAX_REASON_LENGTH
+        ));
+    }
+    if delta != 0 && reason.is_none() && field_policy().require_reason == Some(true) {
+        return Err("Quantity change requires a reason".to_string());
+    }
+    Ok(reason)
+}
+
+//helper method to check that a timber has the fields the field policy requires
+fn check_timber_policy(supplier: &Option<String>, unit_cost: &Option<u64>) -> Result<(), String> {
+    let policy = field_policy();
+    if policy.require_supplier && supplier.is_none() {
+        return Err("Supplier is required".to_string());
+    }
+    if policy.require_cost && unit_cost.is_none() {
+        return Err("Unit cost is required".to_string());
+    }
+    Ok(())
+}
+
+//helper method to get the tier unit price of a timber for a quantity, none if no tier applies
+fn tier_price(timber: &Timber, quantity: u64) -> Option<u64> {
+    timber
+        .pricing_tiers
+        .as_ref()?
+        .iter()
+        .rev()
+        .find(|tier| quantity >= tier.min_quantity)
+        .map(|tier| tier.unit_price)
+}
+
+//helper method to give a sale without a price, or a price of 0, the tier price of its
+//timber, or else its default unit price. a sale priced 0 with neither keeps its price, a
+//sale with no price and neither is rejected. unknown timber is left for check_stock to report
+fn resolve_price(sales: SalesPayload) -> Result<SalesPayload, String> {
+    if sales.price.is_some_and(|price| price != 0) {
+        return Ok(sales);
+    }
+    let Some(timber) = _get_timber(&sales.timber_id) else {
+        return Ok(sales);
+    };
+    match tier_price(&timber, sales.quantity)
+        .or(timber.default_unit_price)
+        .or(sales.price)
+    {
+        Some(price) => Ok(SalesPayload {
+            price: Some(price),
+            ..sales
+        }),
+        None => Err(format!(
+            "Price is required, timber id={} has no default unit price",
+            timber.id
+        )),
+    }
+}
+
+//helper method to check that a change of stock from current to quantity doesn't push it
+//above the max capacity of the timber. stock already over a lowered capacity can still go
+//down
+fn check_capacity(current: u64, quantity: u64, max_capacity: Option<u64>) -> Result<(), String> {
+    match max_capacity {
+        Some(max_capacity)
+            if max_capacity != 0 && quantity > max_capacity && quantity > current =>
+        {
+            Err(format!(
+                "Exceeds capacity {} (current {})",
+                max_capacity, current
+            ))
+        }
+        _ => Ok(()),
+    }
+}
+
+//helper method to check that the quantity decimals of a timber are at most
+//MAX_QUANTITY_DECIMALS
+fn check_quantity_decimals(decimals: Option<u8>) -> Result<(), String> {
+    match decimals {
+        Some(decimals) if decimals > MAX_QUANTITY_DECIMALS => Err(format!(
+            "Quantity decimals must be at most {}",
+            MAX_QUANTITY_DECIMALS
+        )),
+        _ => Ok(()),
+    }
+}
+
+//helper method to write a scaled quantity with its decimal places, e.g. 125 at 2 decimals
+//as 1.25
+fn format_quantity(quantity: u64, decimals: u8) -> String {
+    if decimals == 0 {
+        return quantity.to_string();
+    }
+    let scale = 10u64.pow(decimals as u32);
+    format!(
+        "{}.{:0width$}",
+        quantity / scale,
+        quantity % scale,
+        width = decimals as usize
+    )
+}
+
+//helper method to read a quantity with up to decimals decimal places as a scaled quantity,
+//e.g. 1.25 or 1.3 at 2 decimals as 125 or 130
+fn parse_quantity(text: &str, decimals: u8) -> Result<u64, String> {
+    let invalid = || {
+        format!(
+            "Quantity {} is not a number with at most {} decimals",
+            text, decimals
+        )
+    };
+    let (whole, fraction) = text.split_once('.').unwrap_or((text, ""));
+    let is_digits = |part: &str| part.bytes().all(|byte| byte.is_ascii_digit());
+    if whole.is_empty()
+        || !is_digits(whole)
+        || !is_digits(fraction)
+        || text.ends_with('.')
+        || fraction.len() > decimals as usize
+    {
+        return Err(invalid());
+    }
+    let scale = 10u64.pow(decimals as u32);
+    let fraction_scale = 10u64.pow((decimals as usize - fraction.len()) as u32);
+    let fraction = fraction.parse::<u64>().unwrap_or(0) * fraction_scale;
+    whole
+        .parse::<u64>()
+        .ok()
+        .and_then(|whole| whole.checked_mul(scale))
+        .and_then(|whole| whole.checked_add(fraction))
+        .ok_or_else(invalid)
+}
+
+//helper method to check that a bundle size, when given, is greater than 0
+fn check_bundle_size(bundle_size: Option<u64>) -> Result<Option<u64>, String> {
+    if bundle_size == Some(0) {
+        return Err("Bundle size must be greater than 0".to_string());
+    }
+    Ok(bundle_size)
+}
+
+//helper method to convert the quantity of a sale given in pieces or bundles to the unit of
+//its timber. a price given with the sale is converted too, so the line total stays what the
+//customer was quoted. pieces sold from bundle timber must make whole bundles unless
+//split_bundles is set, in which case every opened bundle leaves the stock. unknown timber is
+//left for check_stock to report
+fn convert_sale_unit(sales: SalesPayload) -> Result<SalesPayload, String> {
+    let (Some(sale_unit), Some(timber)) = (sales.unit, _get_timber(&sales.timber_id)) else {
+        return Ok(sales);
+    };
+    let timber_unit = timber.unit.as_deref().unwrap_or(DEFAULT_UNIT);
+    if timber_unit == sale_unit.as_unit() {
+        return Ok(SalesPayload {
+            unit: None,
+            ..sales
+        });
+    }
+    let bundle_size = match (sale_unit, timber_unit, timber.bundle_size) {
+        (SaleUnit::Piece, "bundle", Some(size)) | (SaleUnit::Bundle, "piece", Some(size)) => size,
+        (_, "bundle", None) | (_, "piece", None) => {
+            return Err(format!(
+                "timber with id={} has no bundle size to convert with",
+                timber.id
+            ))
+        }
+        _ => {
+            return Err(format!(
+                "timber counted in {} can't be sold by the {}",
+                timber_unit,
+                sale_unit.as_unit()
+            ))
+        }
+    };
+    let quantity = match sale_unit {
+        SaleUnit::Bundle => sales
+            .quantity
+            .checked_mul(bundle_size)
+            .ok_or_else(|| "Quantity is too large".to_string())?,
+        SaleUnit::Piece => {
+            if !sales.quantity.is_multiple_of(bundle_size) && sales.split_bundles != Some(true) {
+                return Err(format!(
+                    "{} pieces don't make whole bundles of {}",
+                    sales.quantity, bundle_size
+                ));
+            }
+            sales.quantity.div_ceil(bundle_size)
+        }
+    };
+    let price = match sales.price {
+        Some(price) if quantity != 0 => {
+            let line_total = price as u128 * sales.quantity as u128;
+            let price = apply_rounding(line_total, quantity as u128, rounding_mode());
+            Some(u64::try_from(price).map_err(|_| "Price is too large".to_string())?)
+        }
+        price => price,
+    };
+    Ok(SalesPayload {
+        quantity,
+        price,
+        unit: None,
+        ..sales
+    })
+}
+
+//helper method to check that a timber type is one of the valid timber types
+fn validate_timber_type(timber_type: &str) -> Result<(), String> {
+    if VALID_TIMBER_TYPES.contains(&timber_type) {
+        Ok(())
+    } else {
+        Err(format!("Invalid timber type {}", timber_type))
+    }
+}
+
+//helper method to check that a timber size is one of the valid timber sizes, returning it
+//in its canonical form
+fn validate_timber_size(timber_size: &str) -> Result<String, String> {
+    let canonical = canonical_timber_size(timber_size).ok_or_else(|| {
+        format!(
+            "Invalid timber size {}. sizes must look like WxH, e.g. 2x4",
+            timber_size
+        )
+    })?;
+    if VALID_TIMBER_SIZES.contains(&canonical.as_str()) {
+        Ok(canonical)
+    } else {
+        Err(format!("Invalid timber size {}", timber_size))
+    }
+}
+
+//helper method to bring a size like "2 X 04" into the WxH form the valid sizes use,
+//ignoring whitespace, case and leading zeros. none if it isn't two numbers split by an x
+fn canonical_timber_size(timber_size: &str) -> Option<String> {
+    let size: String = timber_size
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_lowercase();
+    let (width, height) = size.split_once('x')?;
+    let parse = |part: &str| -> Option<u64> {
+        if part.is_empty() || !part.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        part.parse().ok()
+    };
+    Some(format!("{}x{}", parse(width)?, parse(height)?))
+}
+
+//helper method to check that a unit is one of the valid units
+fn validate_unit(unit: String) -> Result<String, String> {
+    if set_contains(&VALID_UNITS, &unit) {
+        Ok(unit)
+    } else {
+        Err(format!(
+            "Invalid unit {}. valid units are {}",
+            unit,
+            set_values(&VALID_UNITS).join(", ")
+        ))
+    }
+}
+
+//helper method to check that a sku is non-empty, short enough to index and not taken in the
+//caller's yard
+fn validate_sku(sku: String) -> Result<String, String> {
+    if sku.trim().is_empty() {
+        return Err("SKU must not be empty".to_string());
+    }
+    if sku.len() > StorableString::MAX_SIZE as usize {
+        return Err(format!(
+            "SKU must be at most {} bytes long",
+            StorableString::MAX_SIZE
+        ));
+    }
+    if SKU_INDEX.with(|index| index.borrow().contains_key(&sku_key(yard(), &sku))) {
+        return Err(format!("SKU {} already exists", sku));
+    }
+    Ok(sku)
+}
+
+//helper method to check that a location is one of the valid locations
+fn validate_location(location: String) -> Result<String, String> {
+    if set_contains(&VALID_LOCATIONS, &location) {
+        Ok(location)
+    } else {
+        Err(format!(
+            "Invalid location {}. valid locations are {}",
+            location,
+            set_values(&VALID_LOCATIONS).join(", ")
+        ))
+    }
+}
+
+//helper method to fill the valid units with the defaults the first time the canister runs.
+//it is recorded once done, so the owner removing every unit doesn't bring the defaults back.
+//canisters seeded before that was recorded still have their units and only get the record
+fn seed_default_units() {
+    if get_setting(&UNITS_SEEDED) != 0 {
+        return;
+    }
+    if VALID_UNITS.with(|set| set.borrow().is_empty()) {
+        for unit in DEFAULT_UNITS {
+            VALID_UNITS.with(|set| set.borrow_mut().insert(StorableString(unit.to_string()), ()));
+        }
+    }
+    set_setting(&UNITS_SEEDED, 1).expect("cannot record that the units are seeded");
+}
+
+//helper method to list the values of a set of valid values
+fn set_values(set: &'static std::thread::LocalKey<RefCell<ValueSet>>) -> Vec<String> {
+    set.with(|set| set.borrow().iter().map(|(value, _)| value.0).collect())
+}
+
+//helper method to check whether a set of valid values contains a value
+fn set_contains(set: &'static std::thread::LocalKey<RefCell<ValueSet>>, value: &str) -> bool {
+    set.with(|set| set.borrow().contains_key(&StorableString(value.to_string())))
+}
+
+//helper method to add a value to a set of valid values
+fn set_insert(
+    set: &'static std::thread::LocalKey<RefCell<ValueSet>>,
+    value: String,
+) -> Result<(), String> {
+    if value.trim().is_empty() {
+        return Err("value must not be empty".to_string());
+    }
+    if value.len() > StorableString::MAX_SIZE as usize {
+        return Err(format!(
+            "value must be at most {} bytes long",
+            StorableString::MAX_SIZE
+        ));
+    }
+    set.with(|set| set.borrow_mut().insert(StorableString(value), ()));
+    Ok(())
+}
+
+//helper method to remove a value from a set of valid values
+fn set_remove(
+    set: &'static std::thread::LocalKey<RefCell<ValueSet>>,
+    value: &str,
+) -> Result<(), String> {
+    match set.with(|set| set.borrow_mut().remove(&StorableString(value.to_string()))) {
+        Some(_) => Ok(()),
+        None => Err(format!("{} is not a valid value", value)),
+    }
+}
+
+//helper method to check whether any timber satisfies the given condition
+fn any_timber(condition: impl Fn(&Timber) -> bool) -> bool {
+    TIMBER_STORAGE.with(|service| service.borrow().iter().any(|(_, timber)| condition(&timber)))
+}
+
+//helper method to check that sales are allowed at the current time of day
+fn check_business_hours() -> Result<(), String> {
+    let start = get_setting(&BUSINESS_HOURS_START_NS);
+    let end = get_setting(&BUSINESS_HOURS_END_NS);
+    if start == end {
+        return Ok(());
+    }
+    let time_of_day = time() % DAY_NS;
+    let open = if start < end {
+        time_of_day >= start && time_of_day < end
+    } else {
+        time_of_day >= start || time_of_day < end
+    };
+    if !open {
+        return Err(format!(
+            "Sales not allowed outside business hours (time of day {} ns)",
+            time_of_day
+        ));
+    }
+    Ok(())
+}
+
+//helper method to check the backdated timestamps of the given sales.
+//only the owner can backdate a sale and it can't be dated in the future
+fn check_occurred_at(items: &[SalesPayload]) -> Result<(), String> {
+    if items.iter().all(|item| item.occurred_at.is_none()) {
+        return Ok(());
+    }
+    if !is_owner() {
+        return Err("only the owner can set occurred_at on a sale".to_string());
+    }
+    for occurred_at in items.iter().filter_map(|item| item.occurred_at) {
+        check_not_future(occurred_at)?;
+    }
+    Ok(())
+}
+
+//helper method to reject an explicitly given timestamp that is after the current time, so
+//a typo can't record an event in the future
+fn check_not_future(timestamp: u64) -> Result<(), String> {
+    if timestamp > time() {
+        return Err(format!("Timestamp {} is in the future", timestamp));
+    }
+    Ok(())
+}
+
+//helper method to check that the line total of a sale, quantity * price, fits in a u64, so
+//no single sale can make the revenue sums overflow
+fn check_line_total(quantity: u64, price: u64) -> Result<(), String> {
+    match quantity.checked_mul(price) {
+        Some(_) => Ok(()),
+        None => Err("Line total overflows".to_string()),
+    }
+}
+
+//helper method to check that a price is within the configured price limits
+fn check_price(price: u64) -> Result<(), String> {
+    let min_price = get_setting(&MIN_PRICE);
+    let max_price = get_setting(&MAX_PRICE);
+    if min_price != 0 && price < min_price {
+        return Err(format!(
+            "Price {} is below the minimum price {}",
+            price, min_price
+        ));
+    }
+    if max_price != 0 && price > max_price {
+        return Err(format!(
+            "Price {} is above the maximum price {}",
+            price, max_price
+        ));
+    }
+    Ok(())
+}
+
+//helper method to check that no sale is priced below the unit cost of its timber, when
+//set_block_below_cost is on. timber without a unit cost can be sold at any price
+fn check_below_cost(items: &[SalesPayload]) -> Result<(), String> {
+    if get_setting(&BLOCK_BELOW_COST) == 0 {
+        return Ok(());
+    }
+    for item in items {
+        if let Some(unit_cost) = _get_timber(&item.timber_id).and_then(|t| t.unit_cost) {
+            if item.unit_price() < unit_cost {
+                return Err(format!(
+                    "Price {} is below unit cost {}",
+                    item.unit_price(),
+                    unit_cost
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+//helper method to check that every sale is a positive multiple of its timber's sale increment.
+//unknown timber is left for check_stock to report
+fn check_sale_increment(items: &[SalesPayload]) -> Result<(), String> {
+    for item in items {
+        if let Some(increment) = _get_timber(&item.timber_id).and_then(|t| t.sale_increment) {
+            if increment > 1 && (item.quantity == 0 || item.quantity % increment != 0) {
+                return Err(format!("Quantity must be a multiple of {}", increment));
+            }
+        }
+    }
+    Ok(())
+}
+
+//helper method to check that no sale takes from a batch with enforce_fifo set while an older
+//batch of the same type and size in the yard has stock left. stock the same sales take from
+//the older batch counts as gone, so an order can empty the older batch and go on to the newer
+fn check_fifo(items: &[SalesPayload]) -> Result<(), String> {
+    let mut requested: HashMap<u64, u64> = HashMap::new();
+    for item in items {
+        let total = requested.entry(item.timber_id).or_insert(0);
+        *total = total.saturating_add(item.quantity);
+    }
+    for item in items {
+        let Some(timber) = _get_timber(&item.timber_id) else {
+            continue;
+        };
+        if timber.enforce_fifo != Some(true) {
+            continue;
+        }
+        let older = partition_timber().into_iter().find(|older| {
+            older.timber_type == timber.timber_type
+                && older.timber_size == timber.timber_size
+                && (older.created_at, older.id) < (timber.created_at, timber.id)
+                && older
+                    .quantity
+                    .saturating_sub(reserved_by_others(older.id))
+                    .saturating_sub(requested.get(&older.id).copied().unwrap_or(0))
+                    > 0
+        });
+        if let Some(older) = older {
+            return Err(format!(
+                "timber with id={} must be sold first, it is an older batch with stock",
+                older.id
+            ));
+        }
+    }
+    Ok(())
+}
+
+//helper method to get the quantity of a timber held by reservations that haven't expired
+fn reserved_quantity(timber_id: u64) -> u64 {
+    let now = time();
+    RESERVATIONS.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .filter(|(_, reservation)| {
+                reservation.timber_id == timber_id && reservation.expires_at > now
+            })
+            .fold(0u64, |total, (_, reservation)| {
+                total.saturating_add(reservation.quantity)
+            })
+    })
+}
+
+//helper method to get the quantity of a timber held by other principals' reservations that
+//haven't expired
+fn reserved_by_others(timber_id: u64) -> u64 {
+    reserved_quantity(timber_id).saturating_sub(
+        own_reservations(timber_id)
+            .iter()
+            .fold(0u64, |total, reservation| {
+                total.saturating_add(reservation.quantity)
+            }),
+    )
+}
+
+//helper method to get the caller's reservations of a timber that haven't expired, oldest first
+fn own_reservations(timber_id: u64) -> Vec<Reservation> {
+    let now = time();
+    let holder = caller();
+    RESERVATIONS.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .map(|(_, reservation)| reservation)
+            .filter(|reservation| {
+                reservation.timber_id == timber_id
+                    && reservation.expires_at > now
+                    && record_tenant(&reservation.holder) == holder
+            })
+            .collect()
+    })
+}
+
+//helper method to take a quantity the caller sold out of their holds on the timber, oldest
+//first. holds used up are removed
+fn do_use_reservations(timber_id: u64, quantity: u64) {
+    let mut left = quantity;
+    for mut reservation in own_reservations(timber_id) {
+        if left == 0 {
+            break;
+        }
+        let used = left.min(reservation.quantity);
+        left -= used;
+        reservation.quantity -= used;
+        RESERVATIONS.with(|service| {
+            let mut service = service.borrow_mut();
+            if reservation.quantity == 0 {
+                service.remove(&reservation.id);
+            } else {
+                service.insert(reservation.id, reservation);
+            }
+        });
+    }
+}
+
+//helper method to remove the reservations that have expired
+fn do_prune_reservations(now: u64) {
+    RESERVATIONS.with(|service| {
+        let expired: Vec<u64> = service
+            .borrow()
+            .iter()
+            .filter(|(_, reservation)| reservation.expires_at <= now)
+            .map(|(id, _)| id)
+            .collect();
+        let mut service = service.borrow_mut();
+        for id in expired {
+            service.remove(&id);
+        }
+    });
+}
+
+//helper method to check that there is enough stock for the given sales, leaving out stock
+//held by other principals' reservations. the caller sells against their own holds, see
+//do_use_reservations
+fn check_stock(items: &[SalesPayload]) -> Result<(), String> {
+    check_available(items, false)
+}
+
+//helper method to check that there is enough stock for the given items, leaving out stock
+//held by reservations, the caller's own only when count_own_holds is set. quantities for the
+//same timber are summed so an order can't oversell across its items
+fn check_available(items: &[SalesPayload], count_own_holds: bool) -> Result<(), String> {
+    let mut requested: BTreeMap<u64, u64> = BTreeMap::new();
+    for item in items {
+        let total = requested.entry(item.timber_id).or_insert(0);
+        *total = total.checked_add(item.quantity).ok_or_else(|| {
+            format!(
+                "requested quantity for timber with id={} is too large",
+                item.timber_id
+            )
+        })?;
+    }
+    for (timber_id, quantity) in requested {
+        let timber = _get_timber(&timber_id)
+            .ok_or_else(|| format!("a timber with id={} not found", timber_id))?;
+        let reserved = if count_own_holds {
+            reserved_quantity(timber_id)
+        } else {
+            reserved_by_others(timber_id)
+        };
+        let available = timber.quantity.saturating_sub(reserved);
+        if available < quantity {
+            return Err(format!(
+                "insufficient stock for timber with id={}. available={}, requested={}",
+                timber_id, available, quantity
+            ));
+        }
+    }
+    Ok(())
+}
+
+//helper method to check a payload as add_sales does and create the sales from it
+fn do_add_sales(payload: SalesPayload) -> Result<Sales, String> {
+    let payload = check_sales_payload(payload)?;
+    let id = generate_unique_id()?;
+    Ok(do_create_sales(id, payload))
+}
+
+//helper method to create a sales and deduct its quantity from the timber stock.
+//the stock must have been checked with check_stock beforehand
+fn do_create_sales(id: u64, payload: SalesPayload) -> Sales {
+    do_deduct_stock(payload.timber_id, payload.quantity);
+    do_use_reservations(payload.timber_id, payload.quantity);
+    let sales = Sales {
+        id,
+        timber_id: payload.timber_id,
+        quantity: payload.quantity,
+        price: payload.unit_price(),
+        customer: payload.customer,
+        created_at: payload.occurred_at.unwrap_or_else(time),
+        updated_at: None,
+        tenant: Some(yard()),
+        is_reversal: None,
+        reversal_of: None,
+        order_id: None,
+        decode_error: None,
+    };
+    do_insert_sales(&sales);
+    sales
+}
+
+//helper method to sum the line totals of the given sales, reversals counting negatively
+fn sales_total(sales: &[Sales]) -> Result<u64, String> {
+    let total = sales.iter().try_fold(0i128, |total, sales| {
+        net_line_total(sales)
+            .and_then(|line_total| total.checked_add(line_total))
+            .ok_or_else(|| "sales total overflows".to_string())
+    })?;
+    u64::try_from(total.max(0)).map_err(|_| "sales total overflows".to_string())
+}
+
+//helper method to get the line total of a sales as it counts towards revenue, negative for
+//a reversal. none if it overflows
+fn net_line_total(sales: &Sales) -> Option<i128> {
+    let line_total = i128::try_from(sales_amount(sales)).ok()?;
+    Some(if is_reversal(sales) {
+        -line_total
+    } else {
+        line_total
+    })
+}
+
+//helper method to get the quantity of a sales as it counts towards units sold, negative for
+//a reversal
+fn net_quantity(sales: &Sales) -> i128 {
+    if is_reversal(sales) {
+        -(sales.quantity as i128)
+    } else {
+        sales.quantity as i128
+    }
+}
+
+//helper method to get how a sales counts towards a number of sales, -1 for a reversal
+fn net_count(sales: &Sales) -> i64 {
+    if is_reversal(sales) {
+        -1
+    } else {
+        1
+    }
+}
+
+//helper method to check whether a sales is the reversal entry of another
+fn is_reversal(sales: &Sales) -> bool {
+    sales.is_reversal == Some(true)
+}
+
+//helper method to get the id of the reversal of a sales, none if it hasn't been reversed
+fn reversed_by(id: u64) -> Option<u64> {
+    REVERSED_SALES.with(|index| index.borrow().get(&id))
+}
+
+//helper method to add a reversal to the reversed sale index. other sales are left out
+fn do_index_reversal(sales: &Sales) {
+    if let (true, Some(reversal_of)) = (is_reversal(sales), sales.reversal_of) {
+        REVERSED_SALES.with(|index| index.borrow_mut().insert(reversal_of, sales.id));
+    }
+}
+
+//helper method to check whether a sales stands as a sale, neither a reversal nor reversed
+fn is_standing_sale(sales: &Sales) -> bool {
+    !is_reversal(sales) && reversed_by(sales.id).is_none()
+}
+
+//helper method to check that a sales is still within the delete grace period, or that the
+//owner is deleting it
+fn check_delete_grace(sales: &Sales) -> Result<(), String> {
+    let grace_ns = get_setting(&DELETE_GRACE_NS);
+    if grace_ns == 0 || is_owner() || time().saturating_sub(sales.created_at) <= grace_ns {
+        return Ok(());
+    }
+    Err("Sale older than grace period; owner action required".to_string())
+}
+
+//helper method to check that a sales is neither a reversal nor reversed, since changing
+//either side would leave the pair out of balance
+fn check_not_reversed(sales: &Sales) -> Result<(), String> {
+    if is_reversal(sales) {
+        return Err(format!("sales id={} is a reversal", sales.id));
+    }
+    match reversed_by(sales.id) {
+        Some(reversal) => Err(format!(
+            "sales id={} has been reversed by sales id={}",
+            sales.id, reversal
+        )),
+        None => Ok(()),
+    }
+}
+
+//helper method to get what quantity units at price come to. the quantities of timber with
+//quantity decimals are stored scaled, so the product is scaled back down and rounded with
+//the rounding mode. every money figure is worked out through this
+fn amount(quantity: u64, price: u64, quantity_decimals: Option<u8>) -> u128 {
+    let decimals = quantity_decimals.unwrap_or(0).min(MAX_QUANTITY_DECIMALS);
+    apply_rounding(
+        quantity as u128 * price as u128,
+        10u128.pow(decimals as u32),
+        rounding_mode(),
+    )
+}
+
+//helper method to get what a sales comes to, see amount. the quantity decimals are those of
+//its timber, none once the timber is deleted
+fn sales_amount(sales: &Sales) -> u128 {
+    let decimals = _get_timber_by_id(&sales.timber_id).and_then(|timber| timber.quantity_decimals);
+    amount(sales.quantity, sales.price, decimals)
+}
+
+//helper method to take a sold quantity out of the timber stock.
+//the stock must have been checked with check_stock beforehand
+fn do_deduct_stock(timber_id: u64, quantity: u64) {
+    if let Some(mut timber) = _get_timber(&timber_id) {
+        let was_low = is_low_stock(&timber);
+        timber.quantity -= quantity;
+        timber.updated_at = Some(time());
+        //the sale may already be half made, so a trap, which undoes the whole call, is safer
+        //than an error here
+        do_insert_timber(&timber).unwrap_or_else(|e| ic_cdk::trap(&e));
+        do_record_movement(timber_id, -to_delta(quantity), "sale");
+        if !was_low {
+            do_notify_low_stock(&timber);
+        }
+    }
+}
+
+//helper method to put a quantity back into the timber stock, used when a sales is removed
+//or reversed.
+//nothing is restored if the timber has been deleted in the meantime or is corrupted
+fn do_restore_stock(timber_id: u64, quantity: u64, reason: &str) {
+    if let Some(mut timber) = _get_timber(&timber_id).filter(|t| t.decode_error.is_none()) {
+        timber.quantity = timber.quantity.saturating_add(quantity);
+        timber.updated_at = Some(time());
+        //as in do_deduct_stock, a trap undoes the half made change
+        do_insert_timber(&timber).unwrap_or_else(|e| ic_cdk::trap(&e));
+        do_record_movement(timber_id, to_delta(quantity), reason);
+    }
+}
+
+//helper method to sort timber by id. partition_timber goes through this, so every query
+//built on it returns timber in id order whatever order the storage happens to iterate in
+fn sort_timber(mut timber: Vec<Timber>) -> Vec<Timber> {
+    timber.sort_by_key(|timber| timber.id);
+    timber
+}
+
+//helper method to sort sales by id, the sales counterpart of sort_timber
+fn sort_sales(mut sales: Vec<Sales>) -> Vec<Sales> {
+    sales.sort_by_key(|sales| sales.id);
+    sales
+}
+
+//helper method to get the value of a timber's stock, zero when it has no unit cost
+fn timber_value(timber: &Timber) -> u64 {
+    let value = amount(
+        timber.quantity,
+        timber.unit_cost.unwrap_or(0),
+        timber.quantity_decimals,
+    );
+    u64::try_from(value).unwrap_or(u64::MAX)
+}
+
+//helper method to check whether a timber is at or below its reorder point
+fn is_low_stock(timber: &Timber) -> bool {
+    matches!(timber.reorder_point, Some(reorder_point) if timber.quantity <= reorder_point)
+}
+
+//helper method to tell the notify canister when a timber is low on stock. the call is made
+//in the background, so a failure to notify is logged and never fails the sale
+fn do_notify_low_stock(timber: &Timber) {
+    let canister = NOTIFY_CANISTER.with(|cell| cell.borrow().get().0);
+    if canister == Principal::anonymous() || !is_low_stock(timber) {
+        return;
+    }
+    let (timber_id, quantity) = (timber.id, timber.quantity);
+    ic_cdk::spawn(async move {
+        let result: ic_cdk::api::call::CallResult<()> =
+            ic_cdk::call(canister, "low_stock_alert", (timber_id, quantity)).await;
+        if let Err((code, message)) = result {
+            if LogLevel::from_setting(get_setting(&LOG_LEVEL)) != LogLevel::Off {
+                ic_cdk::println!(
+                    "[error] low_stock_alert to {} for timber_id={} failed: {:?} {}",
+                    canister,
+                    timber_id,
+                    code,
+                    message
+                );
+            }
+        }
+    });
+}
+
+//helper method to move a sales to the recycle bin and put its quantity back into stock,
+//recording the stock movement with reason
+fn do_delete_sales(sales: &Sales, reason: &str) {
+    SALES_STORAGE.with(|service| service.borrow_mut().remove(&sales.id));
+    SALES_TENANTS.with(|index| index.borrow_mut().remove(&sales.id));
+    DELETED_SALES.with(|service| service.borrow_mut().insert(sales.id, sales.clone()));
+    do_restore_stock(sales.timber_id, sales.quantity, reason);
+}
+
+//helper method to remove a timber together with its sku index entry.
+//the remaining stock is recorded as going out
+fn do_remove_timber(timber: &Timber) {
+    TIMBER_STORAGE.with(|service| service.borrow_mut().remove(&timber.id));
+    TIMBER_TENANTS.with(|index| index.borrow_mut().remove(&timber.id));
+    if timber.quantity > 0 {
+        do_record_movement(timber.id, -to_delta(timber.quantity), "timber deleted");
+    }
+    if let Some(sku) = &timber.sku {
+        let key = sku_key(record_tenant(&timber.tenant), sku);
+        SKU_INDEX.with(|index| index.borrow_mut().remove(&key));
+    }
+}
+
+//helper method to get the sku index key of a sku in the yard of tenant
+fn sku_key(tenant: Principal, sku: &str) -> (StorablePrincipal, StorableString) {
+    (StorablePrincipal(tenant), StorableString(sku.to_string()))
+}
+
+//helper method to record that the price of a sales is changing to new_price
+fn do_record_price_change(sales: &Sales, new_price: u64) -> Result<(), String> {
+    let change = PriceChange {
+        id: generate_unique_id()?,
+        sales_id: sales.id,
+        old_price: sales.price,
+        new_price,
+        changed_at: time(),
+    };
+    PRICE_HISTORY.with(|service| service.borrow_mut().insert(change.id, change));
+    Ok(())
+}
+
+//helper method to convert a quantity to a signed stock movement delta
+fn to_delta(quantity: u64) -> i64 {
+    i64::try_from(quantity).unwrap_or(i64::MAX)
+}
+
+//helper method to record a change to the stock of a timber
+fn do_record_movement(timber_id: u64, delta: i64, reason: &str) {
+    STOCK_MOVEMENTS.with(|service| {
+        let mut service = service.borrow_mut();
+        let number = service
+            .iter_upper_bound(&(timber_id, u64::MAX))
+            .next()
+            .filter(|((id, _), _)| *id == timber_id)
+            .map(|((_, number), _)| number + 1)
+            .unwrap_or(0);
+        let movement = StockMovement {
+            timber_id,
+            delta,
+            reason: reason.to_string(),
+            created_at: time(),
+        };
+        service.insert((timber_id, number), movement);
+    });
+}
+
+//helper method to check whether a record belongs to the caller's yard
+fn in_partition(tenant: &Option<Principal>) -> bool {
+    record_tenant(tenant) == yard()
+}
+
+//helper method to get the principal whose yard the caller works in, see yard_of
+fn yard() -> Principal {
+    yard_of(caller())
+}
+
+//helper method to get the principal whose yard a principal works in. authorized principals
+//are the owner's staff, so they work in the owner's yard, everyone else in their own
+fn yard_of(principal: Principal) -> Principal {
+    if is_authorized(principal) {
+        owner()
+    } else {
+        principal
+    }
+}
+
+//helper method to get the principal whose yard a record belongs to.
+//records from before tenants existed belong to the owner
+fn record_tenant(tenant: &Option<Principal>) -> Principal {
+    tenant.unwrap_or_else(owner)
+}
+
+//helper method to get every timber in the caller's yard, in id order (see sort_timber)
+fn partition_timber() -> Vec<Timber> {
+    let tenant = yard();
+    sort_timber(TIMBER_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .filter(|(_, timber)| record_tenant(&timber.tenant) == tenant)
+            .map(|(id, timber)| keyed_timber(id, timber))
+            .collect()
+    }))
+}
+
+//helper method to get every sales in the caller's yard, in id order (see sort_timber)
+fn partition_sales() -> Vec<Sales> {
+    let tenant = yard();
+    sort_sales(SALES_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .filter(|(_, sales)| record_tenant(&sales.tenant) == tenant)
+            .map(|(id, sales)| keyed_sales(id, sales))
+            .collect()
+    }))
+}
+
+//helper method to run the body of an update method, logging the call with its key arguments
+//and any error it returns at the configured log level. the call is also counted for query_stats
+fn logged<T>(
+    method: &str,
+    args: String,
+    body: impl FnOnce() -> Result<T, String>,
+) -> Result<T, String> {
+    METHOD_CALLS.with(|calls| {
+        let mut calls = calls.borrow_mut();
+        let key = StorableString(method.to_string());
+        let count = calls.get(&key).unwrap_or(0);
+        calls.insert(key, count.saturating_add(1));
+    });
+    let level = LogLevel::from_setting(get_setting(&LOG_LEVEL));
+    if level == LogLevel::Info {
+        ic_cdk::println!("[info] {} caller={} {}", method, caller(), args);
+    }
+    let result = body();
+    if let Err(e) = &result {
+        if level != LogLevel::Off {
+            ic_cdk::println!(
+                "[error] {} caller={} {} error={}",
+                method,
+                caller(),
+                args,
+                e
+            );
+        }
+    }
+    result
+}
+
+//helper method to remove every entry of a stable map
+fn clear_map<K: BoundedStorable + Ord + Clone, V: BoundedStorable>(
+    map: &mut StableBTreeMap<K, V, Memory>,
+) {
+    let keys: Vec<K> = map.iter().map(|(key, _)| key).collect();
+    for key in keys {
+        map.remove(&key);
+    }
+}
+
+//helper method to get the units of a timber sold per day over the last window_ns, rounded
+//to the nearest whole unit
+fn daily_velocity(timber_id: u64, window_ns: u64) -> u64 {
+    let velocity = apply_rounding(
+        units_sold_within(timber_id, window_ns).saturating_mul(DAY_NS as u128),
+        window_ns as u128,
+        RoundingMode::HalfUp,
+    );
+    velocity.min(u64::MAX as u128) as u64
+}
+
+//helper method to get the net units of a timber sold over the last window_ns, at least 0
+fn units_sold_within(timber_id: u64, window_ns: u64) -> u128 {
+    let since = time().saturating_sub(window_ns);
+    let units_sold: i128 = _get_sales_by_timber_id(&timber_id)
+        .iter()
+        .filter(|sales| sales.created_at >= since)
+        .map(net_quantity)
+        .sum();
+    units_sold.max(0) as u128
+}
+
+//helper method to change a price by percent_bps basis points, rounded with the rounding mode.
+//the result is at least 0 and kept within the price limits
+fn adjusted_price(price: u64, percent_bps: i64) -> Result<u64, String> {
+    let scaled = (price as i128)
+        .checked_mul(10_000 + percent_bps as i128)
+        .ok_or("price adjustment overflows".to_string())?;
+    let adjusted = apply_rounding(scaled.max(0) as u128, 10_000, rounding_mode());
+    let adjusted = u64::try_from(adjusted).map_err(|_| "price adjustment overflows".to_string())?;
+    let (min_price, max_price) = (get_setting(&MIN_PRICE), get_setting(&MAX_PRICE));
+    let adjusted = adjusted.max(min_price);
+    Ok(if max_price != 0 {
+        adjusted.min(max_price)
+    } else {
+        adjusted
+    })
+}
+
+//helper method to get the utc weekday of a timestamp, 0 for sunday to 6 for saturday.
+//day 0 of the unix epoch, 1 january 1970, was a thursday
+fn utc_weekday(timestamp_ns: u64) -> u8 {
+    ((timestamp_ns / DAY_NS + 4) % 7) as u8
 }
 
-//a struct to hold the payload for the sales
-#[derive(candid::CandidType, Serialize, Deserialize, Default)]
-struct SalesPayload {
-    timber_id: u64,
-    quantity: u64,
-    price: u64,
+//helper method to get a timber with its type trimmed and lowercased, its size canonical and
+//its other text fields trimmed, none if it is canonical already. the sku is left alone since
+//the sku index is keyed by it
+fn normalized_timber(timber: &Timber) -> Option<Timber> {
+    let trim = |text: &Option<String>| text.as_ref().map(|text| text.trim().to_string());
+    let normalized = Timber {
+        timber_type: timber.timber_type.trim().to_lowercase(),
+        timber_size: canonical_timber_size(&timber.timber_size)
+            .unwrap_or_else(|| timber.timber_size.trim().to_string()),
+        supplier: trim(&timber.supplier),
+        unit: trim(&timber.unit),
+        location: trim(&timber.location),
+        ..timber.clone()
+    };
+    let unchanged = normalized.timber_type == timber.timber_type
+        && normalized.timber_size == timber.timber_size
+        && normalized.supplier == timber.supplier
+        && normalized.unit == timber.unit
+        && normalized.location == timber.location;
+    (!unchanged).then_some(normalized)
 }
 
-//a struct to hold the payload for the sales
-#[derive(candid::CandidType, Serialize, Deserialize, Default)]
-struct SalesUpdatePayload {
-    id: u64,
-    quantity: u64,
-    price: u64,
+//helper method to get a sales with its customer trimmed, none if it is trimmed already
+fn normalized_sales(sales: &Sales) -> Option<Sales> {
+    let customer = sales
+        .customer
+        .as_ref()
+        .map(|customer| customer.trim().to_string());
+    (customer != sales.customer).then(|| Sales {
+        customer,
+        ..sales.clone()
+    })
 }
 
-//a struct to hold the payload for the timber
-#[derive(candid::CandidType, Serialize, Deserialize, Default)]
-struct TimberUpdatePayload {
-    id: u64,
-    timber_type: String,
-    timber_size: String,
-    quantity: u64,
+//helper method to get the (width, height) of a canonical timber size such as 2x4, none for
+//a size that isn't in that form
+fn size_dimensions(timber_size: &str) -> Option<(u64, u64)> {
+    let (width, height) = timber_size.split_once('x')?;
+    Some((width.parse().ok()?, height.parse().ok()?))
 }
 
-//function to get a timber by id
-#[ic_cdk::query]
-fn get_timber(id: u64) -> Result<Timber, String> {
-    match _get_timber(&id) {
-        Some(timber) => Ok(timber),
-        None => Err(format!("a timber with id={} not found", id)),
+//helper method to check a rate limit before it is set
+fn check_rate_limit_setting(calls_per_minute: u64) -> Result<(), String> {
+    if calls_per_minute > MAX_RATE_LIMIT {
+        return Err(format!("the rate limit can be at most {}", MAX_RATE_LIMIT));
     }
+    Ok(())
 }
 
+//helper method to check price limits before they are set
+fn check_price_limits_setting(min_price: u64, max_price: u64) -> Result<(), String> {
+    if min_price != 0 && max_price != 0 && min_price > max_price {
+        return Err("min_price must not be greater than max_price".to_string());
+    }
+    Ok(())
+}
 
-//function to get a sales by id
-#[ic_cdk::query]
-fn get_sales(id: u64) -> Result<Sales, String> {
-    match _get_sales(&id) {
-        Some(sales) => Ok(sales),
-        None => Err(format!("a sales with id={} not found", id)),
+//helper method to check abc thresholds before they are set
+fn check_abc_thresholds_setting(a_percent: u64, b_percent: u64) -> Result<(), String> {
+    if a_percent > b_percent || b_percent > 100 {
+        return Err("thresholds must satisfy a_percent <= b_percent <= 100".to_string());
     }
+    Ok(())
 }
 
-//function to add a timber
-#[ic_cdk::update]
-fn add_timber(timber: TimberPayload) -> Option<Timber> {
-    let id = ID_COUNTER
-        .with(|counter| {
-            let current_value = *counter.borrow().get();
-            counter.borrow_mut().set(current_value + 1)
-        })
-        .expect("cannot increment id counter");
-    let timber = Timber {
-        id,
-        timber_type: timber.timber_type,
-        timber_size: timber.timber_size,
-        quantity: timber.quantity,
-        created_at: time(),
-        updated_at: None,
-    };
-    do_insert_timber(&timber);
-    Some(timber)
+//helper method to check business hours before they are set
+fn check_business_hours_setting(start_ns: u64, end_ns: u64) -> Result<(), String> {
+    if start_ns >= DAY_NS || end_ns >= DAY_NS {
+        return Err(format!("business hours must be less than {} ns", DAY_NS));
+    }
+    Ok(())
 }
 
-//function to add a sales
-#[ic_cdk::update]
-fn add_sales(sales: SalesPayload) -> Option<Sales> {
-    let id = ID_COUNTER
-        .with(|counter| {
-            let current_value = *counter.borrow().get();
-            counter.borrow_mut().set(current_value + 1)
+//helper method to write the field policy
+fn do_set_field_policy(policy: FieldPolicy) -> Result<(), String> {
+    FIELD_POLICY
+        .with(|cell| cell.borrow_mut().set(policy))
+        .map(|_| ())
+        .map_err(|_| "cannot set the field policy".to_string())
+}
+
+//helper method to write the canister told about low stock, none for no canister
+fn do_set_notify_canister(canister: Option<Principal>) -> Result<(), String> {
+    NOTIFY_CANISTER
+        .with(|cell| {
+            cell.borrow_mut().set(StorablePrincipal(
+                canister.unwrap_or_else(Principal::anonymous),
+            ))
         })
-        .expect("cannot increment id counter");
-    let sales = Sales {
-        id,
-        timber_id: sales.timber_id,
-        quantity: sales.quantity,
-        price: sales.price,
-        created_at: time(),
-        updated_at: None,
-    };
-    do_insert_sales(&sales);
-    Some(sales)
+        .map(|_| ())
+        .map_err(|_| "cannot set the notify canister".to_string())
 }
 
-//function to update a timber
-#[ic_cdk::update]
-fn update_timber(id: u64, payload: TimberUpdatePayload) -> Result<Timber, String> {
-    match TIMBER_STORAGE.with(|service| service.borrow().get(&id)) {
-        Some(mut timber) => {
-            timber.timber_type = payload.timber_type;
-            timber.timber_size = payload.timber_size;
-            timber.quantity = payload.quantity;
-            timber.updated_at = Some(time());
-            do_insert_timber(&timber);
-            Ok(timber)
+//helper method to store an order together with the sale to order index entries of its sales.
+//the sales are given the order's id
+fn do_insert_order(order: &Order) {
+    ORDER_STORAGE.with(|service| service.borrow_mut().insert(order.id, order.clone()));
+    SALE_ORDERS.with(|index| {
+        let mut index = index.borrow_mut();
+        for sale_id in &order.sale_ids {
+            index.insert(*sale_id, order.id);
         }
-        None => Err(format!(
-            "couldn't update a timber with id={}. timber not found",
-            id
-        )),
+    });
+    for sale_id in &order.sale_ids {
+        do_set_sale_order(*sale_id, order.id);
     }
 }
 
-//function to update a sales
-#[ic_cdk::update]
-fn update_sales(id: u64, payload: SalesUpdatePayload) -> Result<Sales, String> {
-    match SALES_STORAGE.with(|service| service.borrow().get(&id)) {
-        Some(mut sales) => {
-            sales.quantity = payload.quantity;
-            sales.price = payload.price;
-            sales.updated_at = Some(time());
-            do_insert_sales(&sales);
-            Ok(sales)
+//helper method to remove what refers to sales that are gone for good: their price history,
+//their sale to order index entries and their ids in orders. orders left without sales go
+fn do_forget_sales(ids: &HashSet<u64>) {
+    PRICE_HISTORY.with(|service| {
+        let mut service = service.borrow_mut();
+        let changes: Vec<u64> = service
+            .iter()
+            .filter(|(_, change)| ids.contains(&change.sales_id))
+            .map(|(id, _)| id)
+            .collect();
+        for id in changes {
+            service.remove(&id);
         }
-        None => Err(format!(
-            "couldn't update a sales with id={}. sales not found",
-            id
-        )),
+    });
+    let order_ids: BTreeSet<u64> = SALE_ORDERS.with(|index| {
+        let mut index = index.borrow_mut();
+        ids.iter().filter_map(|id| index.remove(id)).collect()
+    });
+    ORDER_STORAGE.with(|service| {
+        let mut service = service.borrow_mut();
+        for order_id in order_ids {
+            let Some(mut order) = service.get(&order_id) else {
+                continue;
+            };
+            order.sale_ids.retain(|id| !ids.contains(id));
+            if order.sale_ids.is_empty() {
+                service.remove(&order_id);
+            } else {
+                service.insert(order_id, order);
+            }
+        }
+    });
+}
+
+//helper method to give a sales, active or in the recycle bin, the id of its order
+fn do_set_sale_order(sale_id: u64, order_id: u64) {
+    for storage in [&SALES_STORAGE, &DELETED_SALES] {
+        storage.with(|service| {
+            let mut service = service.borrow_mut();
+            if let Some(mut sales) = service.get(&sale_id) {
+                if sales.order_id != Some(order_id) && sales.decode_error.is_none() {
+                    sales.order_id = Some(order_id);
+                    service.insert(sale_id, sales);
+                }
+            }
+        });
     }
 }
 
-//function to delete a timber
-#[ic_cdk::update]
-fn delete_timber(id: u64) -> Result<Timber, String> {
-    match TIMBER_STORAGE.with(|service| service.borrow().get(&id)) {
-        Some(timber) => {
-            TIMBER_STORAGE.with(|service| service.borrow_mut().remove(&id));
-            Ok(timber)
-        }
-        None => Err(format!(
-            "couldn't delete a timber with id={}. timber not found",
-            id
-        )),
+//helper method to give the sales stored before sales had an order_id the id of their order
+fn backfill_sale_orders() {
+    let entries: Vec<(u64, u64)> = SALE_ORDERS.with(|index| index.borrow().iter().collect());
+    for (sale_id, order_id) in entries {
+        do_set_sale_order(sale_id, order_id);
     }
 }
 
-//function to delete a sales
-#[ic_cdk::update]
-fn delete_sales(id: u64) -> Result<Sales, String> {
-    match SALES_STORAGE.with(|service| service.borrow().get(&id)) {
-        Some(sales) => {
-            SALES_STORAGE.with(|service| service.borrow_mut().remove(&id));
-            Ok(sales)
+//helper method to move the skus indexed before skus were kept per yard into SKU_INDEX,
+//under the yard of their timber. the old index is emptied, so this runs once
+fn index_skus_by_yard() {
+    let legacy: Vec<(StorableString, u64)> =
+        LEGACY_SKU_INDEX.with(|index| index.borrow().iter().collect());
+    for (sku, id) in legacy {
+        if let Some(timber) = _get_timber_by_id(&id) {
+            let key = sku_key(record_tenant(&timber.tenant), &sku.0);
+            SKU_INDEX.with(|index| index.borrow_mut().insert(key, id));
         }
-        None => Err(format!(
-            "couldn't delete a sales with id={}. sales not found",
-            id
-        )),
     }
+    LEGACY_SKU_INDEX.with(|index| clear_map(&mut index.borrow_mut()));
+}
+
+//helper method to build the tenant indexes for records stored before them. an index is
+//rebuilt whenever it doesn't cover every record, so it is left alone once in step
+fn index_tenants() {
+    if TIMBER_TENANTS.with(|index| index.borrow().len())
+        != TIMBER_STORAGE.with(|service| service.borrow().len())
+    {
+        TIMBER_TENANTS.with(|index| {
+            let mut index = index.borrow_mut();
+            clear_map(&mut index);
+            TIMBER_STORAGE.with(|service| {
+                for (id, timber) in service.borrow().iter() {
+                    index.insert(id, indexed_tenant(&timber.tenant));
+                }
+            });
+        });
+    }
+    if SALES_TENANTS.with(|index| index.borrow().len())
+        != SALES_STORAGE.with(|service| service.borrow().len())
+    {
+        SALES_TENANTS.with(|index| {
+            let mut index = index.borrow_mut();
+            clear_map(&mut index);
+            SALES_STORAGE.with(|service| {
+                for (id, sales) in service.borrow().iter() {
+                    index.insert(id, indexed_tenant(&sales.tenant));
+                }
+            });
+        });
+    }
+}
+
+//helper method to build the reversed sale index for reversals stored before it existed. it
+//is only built when empty, like the sale to order index
+fn index_reversals() {
+    if !REVERSED_SALES.with(|index| index.borrow().is_empty()) {
+        return;
+    }
+    let reversals: Vec<Sales> = SALES_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .map(|(_, sales)| sales)
+            .filter(is_reversal)
+            .collect()
+    });
+    for reversal in &reversals {
+        do_index_reversal(reversal);
+    }
+}
+
+//helper method to build the sale to order index for orders stored before it existed. it is
+//only built when empty, so upgrades after the first are left with the index they have
+fn index_order_sales() {
+    if !SALE_ORDERS.with(|index| index.borrow().is_empty()) {
+        return;
+    }
+    let orders: Vec<Order> =
+        ORDER_STORAGE.with(|service| service.borrow().iter().map(|(_, order)| order).collect());
+    for order in &orders {
+        do_insert_order(order);
+    }
+}
+
+//helper method to cancel a single sales for bulk_cancel_sales, with the checks of delete_sales
+fn do_cancel_sales(id: u64, reason: &str) -> Result<Sales, String> {
+    let Some(sales) = _get_sales(&id) else {
+        let cancelled = DELETED_SALES.with(|service| {
+            matches!(service.borrow().get(&id), Some(sales) if in_partition(&sales.tenant))
+        });
+        return Err(if cancelled {
+            format!("sales id={} is already cancelled", id)
+        } else {
+            format!("a sales with id={} not found", id)
+        });
+    };
+    check_not_reversed(&sales)?;
+    check_delete_grace(&sales)?;
+    do_delete_sales(&sales, reason);
+    Ok(sales)
+}
+
+//helper method to get the stock a timber should have: what its stock movements say came in,
+//less the net quantity of its sales. an import already counts the sales made before it.
+//none when the ledger doesn't start with the timber coming in, as for timber added before
+//stock movements were recorded
+fn expected_stock(timber_id: u64, sales: &[Sales]) -> Option<i128> {
+    let movements: Vec<StockMovement> = STOCK_MOVEMENTS.with(|service| {
+        service
+            .borrow()
+            .range((timber_id, 0)..=(timber_id, u64::MAX))
+            .map(|(_, movement)| movement)
+            .collect()
+    });
+    let opened_at = match movements.first()? {
+        movement if movement.reason == "import" => movement.created_at,
+        movement if movement.reason == "intake" || movement.reason == "split" => 0,
+        _ => return None,
+    };
+    let received: i128 = movements
+        .iter()
+        .filter(|movement| !is_sale_movement(&movement.reason))
+        .map(|movement| movement.delta as i128)
+        .sum();
+    let sold: i128 = sales
+        .iter()
+        .filter(|sales| sales.created_at >= opened_at)
+        .map(net_quantity)
+        .sum();
+    Some(received - sold)
 }
 
+//helper method to check whether a stock movement was made by a sale, by undoing one or by
+//reconcile_stock_from_sales, rather than by stock coming in or being counted
+fn is_sale_movement(reason: &str) -> bool {
+    matches!(
+        reason,
+        "sale" | "sale updated" | "sale reversed" | "sale deleted" | "reconciled"
+    ) || reason.starts_with("sale cancelled: ")
+}
 
-//helper method to perform insert.
-fn do_insert_timber(timber: &Timber) {
+//helper method to perform insert. a record that would encode past Timber::MAX_SIZE is
+//rejected here, where the stable map would trap
+fn do_insert_timber(timber: &Timber) -> Result<(), String> {
+    //a record that failed to decode holds only defaults, writing it back would lose its bytes
+    if timber.decode_error.is_some() {
+        return Err(format!(
+            "a timber with id={} is corrupted and can't be written",
+            timber.id
+        ));
+    }
+    check_timber_size(timber)?;
     TIMBER_STORAGE.with(|service| service.borrow_mut().insert(timber.id, timber.clone()));
+    TIMBER_TENANTS.with(|index| {
+        index
+            .borrow_mut()
+            .insert(timber.id, indexed_tenant(&timber.tenant))
+    });
+    Ok(())
+}
+
+//helper method to check that a timber encodes within Timber::MAX_SIZE, for callers that
+//must know every insert will succeed before making any
+fn check_timber_size(timber: &Timber) -> Result<(), String> {
+    let size = Encode!(timber)
+        .map_err(|e| format!("cannot encode a timber with id={}: {}", timber.id, e))?
+        .len();
+    if size > Timber::MAX_SIZE as usize {
+        return Err(format!(
+            "timber with id={} is {} bytes, more than the {} bytes a record can hold",
+            timber.id,
+            size,
+            Timber::MAX_SIZE
+        ));
+    }
+    Ok(())
 }
 
-//helper method to perform insert.
+//helper method to perform insert. the write methods reject corrupted sales before getting
+//here, so one reaching this is a bug and the call is undone
 fn do_insert_sales(sales: &Sales) {
+    if sales.decode_error.is_some() {
+        ic_cdk::trap(&format!(
+            "a sales with id={} is corrupted and can't be written",
+            sales.id
+        ));
+    }
     SALES_STORAGE.with(|service| service.borrow_mut().insert(sales.id, sales.clone()));
+    SALES_TENANTS.with(|index| {
+        index
+            .borrow_mut()
+            .insert(sales.id, indexed_tenant(&sales.tenant))
+    });
+}
+
+//helper method to get how a tenant is kept in a tenant index
+fn indexed_tenant(tenant: &Option<Principal>) -> StorablePrincipal {
+    StorablePrincipal(tenant.unwrap_or_else(Principal::anonymous))
+}
+
+//helper method to check whether a tenant index has a record in the caller's yard
+fn indexed_in_partition(
+    index: &'static std::thread::LocalKey<RefCell<TenantIndex>>,
+    id: u64,
+) -> bool {
+    index
+        .with(|index| index.borrow().get(&id))
+        .is_some_and(|tenant| {
+            let tenant = (tenant.0 != Principal::anonymous()).then_some(tenant.0);
+            in_partition(&tenant)
+        })
 }
 
-//helper method to get a timber by id. used in get_timber/update_timber
+//helper method to get a timber by id. used in get_timber/update_timber.
+//timber in another tenant's yard is treated as missing
 fn _get_timber(id: &u64) -> Option<Timber> {
-    TIMBER_STORAGE.with(|service| service.borrow().get(id))
+    TIMBER_STORAGE
+        .with(|service| service.borrow().get(id))
+        .map(|timber| keyed_timber(*id, timber))
+        .filter(|timber| in_partition(&timber.tenant))
 }
 
-//helper method to get a sales by id. used in get_sales/update_sales
+//helper method to get a sales by id. used in get_sales/update_sales.
+//sales in another tenant's yard are treated as missing
 fn _get_sales(id: &u64) -> Option<Sales> {
-    SALES_STORAGE.with(|service| service.borrow().get(id))
+    SALES_STORAGE
+        .with(|service| service.borrow().get(id))
+        .map(|sales| keyed_sales(*id, sales))
+        .filter(|sales| in_partition(&sales.tenant))
+}
+
+//helper method to give a timber that failed to decode back the id it is stored under. the
+//decoded defaults have id 0, which would make deleting it remove the wrong key
+fn keyed_timber(id: u64, timber: Timber) -> Timber {
+    if timber.decode_error.is_some() {
+        Timber { id, ..timber }
+    } else {
+        timber
+    }
+}
+
+//helper method to give a sales that failed to decode back the id it is stored under, see
+//keyed_timber
+fn keyed_sales(id: u64, sales: Sales) -> Sales {
+    if sales.decode_error.is_some() {
+        Sales { id, ..sales }
+    } else {
+        sales
+    }
 }
 
 //helper method to get a sales by id. used in get_sales/update_sales
@@ -337,19 +5717,6 @@ fn _get_sales_by_timber_id(timber_id: &u64) -> Vec<Sales> {
         })
 }
 
-//helper method to get a sales by id. used in get_sales/update_sales
-fn _get_sales_by_price(price: &u64) -> Vec<Sales> {
-    SALES_STORAGE
-        .with(|service| {
-            service
-                .borrow()
-                .iter()
-                .filter(|(_, sales)| sales.price == *price)
-                .map(|(_, sales)| sales.clone())
-                .collect()
-        })
-}
-
 //helper method to get a sales by id. used in get_sales/update_sales
 fn _get_sales_by_quantity(quantity: &u64) -> Vec<Sales> {
     SALES_STORAGE
@@ -497,6 +5864,842 @@ fn _get_timber_by_type_and_size_and_quantity(timber_type: &str, timber_size: &st
 }
 
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    thread_local! {
+        static NOW: std::cell::Cell<u64> = const { std::cell::Cell::new(1_709_424_000_000_000_000) };
+        static CALLER: std::cell::Cell<u8> = const { std::cell::Cell::new(1) };
+    }
+
+    //the clock the canister reads in tests, see advance_time
+    pub(super) fn time() -> u64 {
+        NOW.with(|now| now.get())
+    }
+
+    //the principal making the calls in tests, see call_as
+    pub(super) fn caller() -> Principal {
+        Principal::from_slice(&[CALLER.with(|caller| caller.get())])
+    }
+
+    fn advance_time(ns: u64) {
+        NOW.with(|now| now.set(now.get() + ns));
+    }
+
+    //makes the following calls as the test principal numbered n. init makes principal 1 the
+    //owner
+    fn call_as(n: u8) -> Principal {
+        CALLER.with(|caller| caller.set(n));
+        caller()
+    }
+
+    //checks the stock of a timber and that its stock movements add up to it
+    fn assert_stock(timber_id: u64, quantity: u64) {
+        assert_eq!(get_timber(timber_id).unwrap().quantity, quantity);
+        let moved: i64 = get_stock_movements(timber_id)
+            .iter()
+            .map(|movement| movement.delta)
+            .sum();
+        assert_eq!(moved, to_delta(quantity));
+    }
+
+    #[test]
+    fn stock_follows_sales_through_their_lifecycle() {
+        init();
+        //printing needs the system api too
+        set_setting(&LOG_LEVEL, LogLevel::Off.to_setting()).unwrap();
+        let timber = add_timber(TimberPayload {
+            timber_type: "pine".to_string(),
+            timber_size: "2x4".to_string(),
+            quantity: 100,
+            default_unit_price: Some(50),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_stock(timber.id, 100);
+
+        let sell = |quantity| {
+            advance_time(1_000_000_000);
+            add_sales(SalesPayload {
+                timber_id: timber.id,
+                quantity,
+                ..Default::default()
+            })
+            .unwrap()
+        };
+        let first = sell(30);
+        assert_eq!(first.price, 50);
+        assert_stock(timber.id, 70);
+        let second = sell(20);
+        assert_stock(timber.id, 50);
+        assert!(add_sales(SalesPayload {
+            timber_id: timber.id,
+            quantity: 51,
+            ..Default::default()
+        })
+        .is_err());
+        assert_stock(timber.id, 50);
+
+        let updated = update_sales(
+            first.id,
+            SalesUpdatePayload {
+                id: first.id,
+                quantity: first.quantity,
+                price: 45,
+            },
+        )
+        .unwrap();
+        assert_eq!(updated.price, 45);
+        assert_stock(timber.id, 50);
+
+        let update_quantity = |quantity| {
+            update_sales(
+                first.id,
+                SalesUpdatePayload {
+                    id: first.id,
+                    quantity,
+                    price: 45,
+                },
+            )
+        };
+        update_quantity(40).unwrap();
+        assert_stock(timber.id, 40);
+        assert!(update_quantity(81).is_err());
+        assert_stock(timber.id, 40);
+        update_quantity(30).unwrap();
+        assert_stock(timber.id, 50);
+
+        let reversal = reverse_sale(first.id).unwrap();
+        assert_eq!(reversal.reversal_of, Some(first.id));
+        assert_stock(timber.id, 80);
+        assert!(delete_sales(first.id).is_err());
+        assert!(reverse_sale(first.id).is_err());
+        assert_stock(timber.id, 80);
+        assert_eq!(sales_count_by_timber(), vec![(timber.id, 1)]);
+        assert_eq!(list_timber_with_sales_count(0, 10)[0].1, 1);
+        assert_eq!(sales_summary_for_timber(timber.id).unwrap().transactions, 1);
+        let totals: Vec<i64> = list_sales_with_totals(0, 10)
+            .iter()
+            .map(|(_, total)| *total)
+            .collect();
+        assert_eq!(totals, vec![1350, 1000, -1350]);
+
+        delete_sales(second.id).unwrap();
+        assert_stock(timber.id, 100);
+        assert!(delete_sales(second.id).is_err());
+        assert_stock(timber.id, 100);
+    }
+
+    #[test]
+    fn floor_rounds_towards_zero() {
+        assert_eq!(apply_rounding(10, 4, RoundingMode::Floor), 2);
+        assert_eq!(apply_rounding(11, 4, RoundingMode::Floor), 2);
+        assert_eq!(apply_rounding(8, 4, RoundingMode::Floor), 2);
+    }
+
+    #[test]
+    fn ceil_rounds_any_remainder_up() {
+        assert_eq!(apply_rounding(9, 4, RoundingMode::Ceil), 3);
+        assert_eq!(apply_rounding(8, 4, RoundingMode::Ceil), 2);
+        assert_eq!(apply_rounding(0, 4, RoundingMode::Ceil), 0);
+    }
+
+    #[test]
+    fn half_up_rounds_exact_halves_up() {
+        assert_eq!(apply_rounding(10, 4, RoundingMode::HalfUp), 3);
+        assert_eq!(apply_rounding(5, 2, RoundingMode::HalfUp), 3);
+        assert_eq!(apply_rounding(1, 2, RoundingMode::HalfUp), 1);
+    }
+
+    #[test]
+    fn half_up_rounds_to_the_nearest_value() {
+        assert_eq!(apply_rounding(9, 4, RoundingMode::HalfUp), 2);
+        assert_eq!(apply_rounding(11, 4, RoundingMode::HalfUp), 3);
+        assert_eq!(apply_rounding(1, 3, RoundingMode::HalfUp), 0);
+        assert_eq!(apply_rounding(2, 3, RoundingMode::HalfUp), 1);
+    }
+
+    #[test]
+    fn rounding_handles_the_largest_values() {
+        assert_eq!(
+            apply_rounding(u128::MAX, 2, RoundingMode::HalfUp),
+            u128::MAX / 2 + 1
+        );
+        assert_eq!(apply_rounding(u128::MAX, u128::MAX, RoundingMode::Ceil), 1);
+    }
+
+    #[test]
+    fn rounding_mode_survives_its_setting_value() {
+        for mode in [
+            RoundingMode::Floor,
+            RoundingMode::Ceil,
+            RoundingMode::HalfUp,
+        ] {
+            assert_eq!(RoundingMode::from_setting(mode.to_setting()), mode);
+        }
+    }
+
+    #[test]
+    fn timber_sizes_are_canonicalized() {
+        for size in ["2x4", "2 x 4", "2X4", "02x04", " 2 X 004 "] {
+            assert_eq!(validate_timber_size(size), Ok("2x4".to_string()));
+        }
+    }
+
+    #[test]
+    fn malformed_timber_sizes_are_rejected() {
+        for size in ["", "2", "2x", "x4", "2x4x6", "2*4", "+2x4", "2.5x4"] {
+            assert_eq!(canonical_timber_size(size), None);
+        }
+        assert!(validate_timber_size("3x3").is_err());
+    }
+
+    #[test]
+    fn default_prices_adjust_by_basis_points() {
+        assert_eq!(adjusted_price(1000, 250), Ok(1025));
+        assert_eq!(adjusted_price(1000, -1000), Ok(900));
+        assert_eq!(adjusted_price(1000, -20_000), Ok(0));
+        assert!(adjusted_price(u64::MAX, 10_000).is_err());
+    }
+
+    #[test]
+    fn oversized_timber_is_rejected_instead_of_trapping() {
+        let mut timber = Timber {
+            id: 1,
+            timber_type: "pine".to_string(),
+            timber_size: "2x4".to_string(),
+            supplier: Some(String::new()),
+            ..Default::default()
+        };
+        let base_size = Encode!(&timber).unwrap().len();
+        //a supplier this long takes a two byte length prefix instead of one
+        timber.supplier = Some("x".repeat(Timber::MAX_SIZE as usize - base_size));
+        assert_eq!(
+            Encode!(&timber).unwrap().len(),
+            Timber::MAX_SIZE as usize + 1
+        );
+        assert!(do_insert_timber(&timber).is_err());
+        assert!(TIMBER_STORAGE.with(|service| service.borrow().get(&1).is_none()));
+    }
+
+    #[test]
+    fn line_totals_that_overflow_are_rejected() {
+        assert_eq!(check_line_total(u64::MAX, 1), Ok(()));
+        assert_eq!(check_line_total(u64::MAX / 2, 2), Ok(()));
+        assert_eq!(
+            check_line_total(u64::MAX / 2 + 1, 2),
+            Err("Line total overflows".to_string())
+        );
+        assert!(check_line_total(u64::MAX, u64::MAX).is_err());
+    }
+
+    #[test]
+    fn reversals_cancel_out_the_sales_they_reverse() {
+        let sale = Sales {
+            id: 1,
+            quantity: 3,
+            price: 50,
+            ..Default::default()
+        };
+        let reversal = Sales {
+            id: 2,
+            is_reversal: Some(true),
+            reversal_of: Some(1),
+            ..sale.clone()
+        };
+        assert_eq!(sales_total(std::slice::from_ref(&sale)), Ok(150));
+        assert_eq!(sales_total(&[sale.clone(), reversal.clone()]), Ok(0));
+        assert_eq!(net_quantity(&sale) + net_quantity(&reversal), 0);
+    }
+
+    #[test]
+    fn reconciling_takes_out_stock_for_sales_that_never_did() {
+        init();
+        set_setting(&LOG_LEVEL, LogLevel::Off.to_setting()).unwrap();
+        let timber = add_timber(TimberPayload {
+            timber_type: "pine".to_string(),
+            timber_size: "2x4".to_string(),
+            quantity: 100,
+            ..Default::default()
+        })
+        .unwrap();
+        add_sales(SalesPayload {
+            timber_id: timber.id,
+            quantity: 30,
+            price: Some(10),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(reconcile_stock_from_sales(false), Ok(0));
+        //a sale from before sales deducted stock
+        do_insert_sales(&Sales {
+            id: generate_unique_id().unwrap(),
+            timber_id: timber.id,
+            quantity: 10,
+            price: 10,
+            tenant: Some(caller()),
+            ..Default::default()
+        });
+        assert_eq!(reconcile_stock_from_sales(false), Ok(1));
+        assert_stock(timber.id, 70);
+        assert_eq!(reconcile_stock_from_sales(true), Ok(1));
+        assert_stock(timber.id, 60);
+        assert_eq!(reconcile_stock_from_sales(false), Ok(0));
+    }
+
+    #[test]
+    fn fifo_batches_sell_oldest_first() {
+        init();
+        set_setting(&LOG_LEVEL, LogLevel::Off.to_setting()).unwrap();
+        let batch = |quantity| {
+            advance_time(1_000_000_000);
+            add_timber(TimberPayload {
+                timber_type: "pine".to_string(),
+                timber_size: "2x4".to_string(),
+                quantity,
+                default_unit_price: Some(10),
+                enforce_fifo: Some(true),
+                ..Default::default()
+            })
+            .unwrap()
+        };
+        let sell = |timber_id, quantity| {
+            add_sales(SalesPayload {
+                timber_id,
+                quantity,
+                ..Default::default()
+            })
+        };
+        let older = batch(5);
+        let newer = batch(20);
+        assert_eq!(
+            sell(newer.id, 1).err(),
+            Some(format!(
+                "timber with id={} must be sold first, it is an older batch with stock",
+                older.id
+            ))
+        );
+        sell(older.id, 5).unwrap();
+        sell(newer.id, 1).unwrap();
+        assert_stock(newer.id, 19);
+    }
+
+    #[test]
+    fn bundle_sales_keep_the_quoted_line_total() {
+        init();
+        set_setting(&LOG_LEVEL, LogLevel::Off.to_setting()).unwrap();
+        let timber = add_timber(TimberPayload {
+            timber_type: "pine".to_string(),
+            timber_size: "2x4".to_string(),
+            quantity: 100,
+            bundle_size: Some(10),
+            ..Default::default()
+        })
+        .unwrap();
+        let sale = add_sales(SalesPayload {
+            timber_id: timber.id,
+            quantity: 2,
+            price: Some(500),
+            unit: Some(SaleUnit::Bundle),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!((sale.quantity, sale.price), (20, 50));
+        assert_eq!(net_line_total(&sale), Some(1000));
+        assert_stock(timber.id, 80);
+    }
+
+    #[test]
+    fn money_figures_scale_down_quantities_with_decimals() {
+        init();
+        set_setting(&LOG_LEVEL, LogLevel::Off.to_setting()).unwrap();
+        //10.00 metres at 3 a metre
+        let timber = add_timber(TimberPayload {
+            timber_type: "pine".to_string(),
+            timber_size: "2x4".to_string(),
+            quantity: 1000,
+            unit_cost: Some(3),
+            quantity_decimals: Some(2),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(timber_value(&timber), 30);
+        //2.50 metres at 4 a metre
+        let sale = add_sales(SalesPayload {
+            timber_id: timber.id,
+            quantity: 250,
+            price: Some(4),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(net_line_total(&sale), Some(10));
+        assert_eq!(sales_total(std::slice::from_ref(&sale)), Ok(10));
+        assert_eq!(revenue_in_range(0, u64::MAX), Ok(10));
+    }
+
+    #[test]
+    fn reserved_stock_is_sold_only_by_its_holder() {
+        init();
+        set_setting(&LOG_LEVEL, LogLevel::Off.to_setting()).unwrap();
+        let timber = add_timber(TimberPayload {
+            timber_type: "pine".to_string(),
+            timber_size: "2x4".to_string(),
+            quantity: 10,
+            default_unit_price: Some(5),
+            ..Default::default()
+        })
+        .unwrap();
+        let sell = |quantity| {
+            add_sales(SalesPayload {
+                timber_id: timber.id,
+                quantity,
+                ..Default::default()
+            })
+        };
+        let staff = call_as(2);
+        call_as(1);
+        add_authorized_principal(staff).unwrap();
+        assert!(batch_reserve(vec![(timber.id, 8)], MAX_RESERVATION_TTL_NS + 1).is_err());
+        let hold = batch_reserve(vec![(timber.id, 8)], DAY_NS)
+            .unwrap()
+            .remove(0);
+        assert!(batch_reserve(vec![(timber.id, 3)], DAY_NS).is_err());
+
+        call_as(2);
+        assert!(release_reservation(hold.id).is_err());
+        call_as(3);
+        assert!(batch_reserve(vec![(timber.id, 1)], DAY_NS).is_err());
+
+        call_as(1);
+        sell(5).unwrap();
+        assert_eq!(reserved_quantity(timber.id), 3);
+        sell(5).unwrap();
+        assert_eq!(reserved_quantity(timber.id), 0);
+        assert_stock(timber.id, 0);
+    }
+
+    #[test]
+    fn reversals_come_off_the_order_total() {
+        init();
+        set_setting(&LOG_LEVEL, LogLevel::Off.to_setting()).unwrap();
+        let timber = add_timber(TimberPayload {
+            timber_type: "pine".to_string(),
+            timber_size: "2x4".to_string(),
+            quantity: 10,
+            default_unit_price: Some(50),
+            ..Default::default()
+        })
+        .unwrap();
+        let item = SalesPayload {
+            timber_id: timber.id,
+            quantity: 1,
+            ..Default::default()
+        };
+        let order = create_order("ann".to_string(), vec![item.clone(), item]).unwrap();
+        assert_eq!(order_total(order.id), Ok(100));
+        let reversal = reverse_sale(order.sale_ids[0]).unwrap();
+        assert_eq!(order_total(order.id), Ok(50));
+        let sales = get_order(order.id).unwrap().sales;
+        assert_eq!(sales.last().map(|sales| sales.id), Some(reversal.id));
+    }
+
+    #[test]
+    fn merged_sales_point_at_their_merged_order() {
+        init();
+        set_setting(&LOG_LEVEL, LogLevel::Off.to_setting()).unwrap();
+        let timber = add_timber(TimberPayload {
+            timber_type: "pine".to_string(),
+            timber_size: "2x4".to_string(),
+            quantity: 10,
+            default_unit_price: Some(50),
+            ..Default::default()
+        })
+        .unwrap();
+        let item = SalesPayload {
+            timber_id: timber.id,
+            quantity: 1,
+            ..Default::default()
+        };
+        let order = create_order("ann".to_string(), vec![item.clone(), item]).unwrap();
+
+        import_snapshot(export_snapshot().unwrap(), true).unwrap();
+        let merged: Vec<Sales> = partition_sales()
+            .into_iter()
+            .filter(|sales| !order.sale_ids.contains(&sales.id))
+            .collect();
+        assert_eq!(merged.len(), 2);
+        let merged_order = merged[0].order_id.unwrap();
+        assert_ne!(merged_order, order.id);
+        let mut sale_ids: Vec<u64> = merged.iter().map(|sales| sales.id).collect();
+        sale_ids.sort();
+        assert_eq!(get_order(merged_order).unwrap().order.sale_ids, sale_ids);
+        assert!(merged
+            .iter()
+            .all(|sales| sales.order_id == Some(merged_order)));
+        assert_eq!(get_order(order.id).unwrap().order.sale_ids, order.sale_ids);
+
+        import_snapshot(
+            Encode!(&Snapshot {
+                timber: vec![timber],
+                sales: merged,
+                orders: None,
+            })
+            .unwrap(),
+            false,
+        )
+        .unwrap();
+        assert!(partition_sales()
+            .iter()
+            .all(|sales| sales.order_id.is_none()));
+    }
+
+    #[test]
+    fn slow_sellers_still_get_a_restock_suggestion() {
+        init();
+        set_setting(&LOG_LEVEL, LogLevel::Off.to_setting()).unwrap();
+        let timber = add_timber(TimberPayload {
+            timber_type: "oak".to_string(),
+            timber_size: "2x4".to_string(),
+            quantity: 3,
+            default_unit_price: Some(50),
+            reorder_point: Some(1),
+            ..Default::default()
+        })
+        .unwrap();
+        add_sales(SalesPayload {
+            timber_id: timber.id,
+            quantity: 3,
+            ..Default::default()
+        })
+        .unwrap();
+        //3 units over 30 days is 0.1 a day, 0.7 over the 7 day lead time
+        let suggestions = restock_suggestions();
+        assert_eq!(suggestions.len(), 1);
+        assert_eq!(suggestions[0].suggested_order_quantity, 1);
+    }
+
+    #[test]
+    fn staff_work_in_the_owners_yard() {
+        init();
+        set_setting(&LOG_LEVEL, LogLevel::Off.to_setting()).unwrap();
+        let owner = caller();
+        let timber = add_timber(TimberPayload {
+            timber_type: "pine".to_string(),
+            timber_size: "2x4".to_string(),
+            quantity: 10,
+            default_unit_price: Some(50),
+            ..Default::default()
+        })
+        .unwrap();
+        let staff = call_as(2);
+        call_as(1);
+        add_authorized_principal(staff).unwrap();
+
+        call_as(2);
+        assert_eq!(get_timber(timber.id).unwrap().id, timber.id);
+        let sale = add_sales(SalesPayload {
+            timber_id: timber.id,
+            quantity: 1,
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(sale.tenant, Some(owner));
+
+        call_as(3);
+        assert!(get_timber(timber.id).is_err());
+        assert!(get_sales(sale.id).is_err());
+
+        assert!(revert_last_sale().is_err());
+
+        call_as(1);
+        assert_eq!(get_sales(sale.id).unwrap().id, sale.id);
+        let last = add_sales(SalesPayload {
+            timber_id: timber.id,
+            quantity: 2,
+            ..Default::default()
+        })
+        .unwrap();
+        call_as(2);
+        assert_eq!(revert_last_sale().unwrap().id, last.id);
+        call_as(1);
+        assert_stock(timber.id, 9);
+    }
+
+    #[test]
+    fn yards_pick_their_skus_independently() {
+        init();
+        set_setting(&LOG_LEVEL, LogLevel::Off.to_setting()).unwrap();
+        let add = || {
+            add_timber(TimberPayload {
+                timber_type: "pine".to_string(),
+                timber_size: "2x4".to_string(),
+                quantity: 10,
+                sku: Some("PINE-24".to_string()),
+                ..Default::default()
+            })
+        };
+        let ours = add().unwrap();
+        assert!(add().is_err());
+        call_as(2);
+        let theirs = add().unwrap();
+        assert_eq!(
+            get_timber_by_sku("PINE-24".to_string()).unwrap().id,
+            theirs.id
+        );
+        delete_timber(theirs.id).unwrap();
+        assert!(get_timber_by_sku("PINE-24".to_string()).is_err());
+        call_as(1);
+        assert_eq!(
+            get_timber_by_sku("PINE-24".to_string()).unwrap().id,
+            ours.id
+        );
+    }
+
+    #[test]
+    fn restored_sales_count_towards_the_record_limit() {
+        init();
+        set_setting(&LOG_LEVEL, LogLevel::Off.to_setting()).unwrap();
+        let timber = add_timber(TimberPayload {
+            timber_type: "pine".to_string(),
+            timber_size: "2x4".to_string(),
+            quantity: 10,
+            default_unit_price: Some(50),
+            ..Default::default()
+        })
+        .unwrap();
+        set_record_limits(0, 1).unwrap();
+        let sell = || {
+            add_sales(SalesPayload {
+                timber_id: timber.id,
+                quantity: 1,
+                ..Default::default()
+            })
+        };
+        let deleted = sell().unwrap();
+        delete_sales(deleted.id).unwrap();
+        sell().unwrap();
+        assert!(restore_sales(deleted.id).is_err());
+        assert_stock(timber.id, 9);
+        assert_eq!(list_deleted_sales().len(), 1);
+    }
+
+    #[test]
+    fn batches_past_the_record_limit_are_rejected_whole() {
+        init();
+        set_setting(&LOG_LEVEL, LogLevel::Off.to_setting()).unwrap();
+        let timber = add_timber(TimberPayload {
+            timber_type: "pine".to_string(),
+            timber_size: "2x4".to_string(),
+            quantity: 10,
+            default_unit_price: Some(50),
+            ..Default::default()
+        })
+        .unwrap();
+        set_record_limits(0, 3).unwrap();
+        let item = SalesPayload {
+            timber_id: timber.id,
+            quantity: 1,
+            ..Default::default()
+        };
+        add_sales(item.clone()).unwrap();
+        add_sales(item.clone()).unwrap();
+        let backup = export_snapshot().unwrap();
+
+        let limit = Some("Record limit reached".to_string());
+        assert_eq!(
+            create_order("ann".to_string(), vec![item.clone(), item]).err(),
+            limit
+        );
+        assert_eq!(import_snapshot(backup, true).err(), limit);
+        assert_eq!(sales_count(), 2);
+        assert_eq!(timber_count(), 1);
+        assert_stock(timber.id, 8);
+    }
+
+    #[test]
+    fn removed_units_stay_removed_across_upgrades() {
+        init();
+        set_setting(&LOG_LEVEL, LogLevel::Off.to_setting()).unwrap();
+        for unit in list_valid_units() {
+            remove_valid_unit(unit).unwrap();
+        }
+        post_upgrade();
+        assert!(list_valid_units().is_empty());
+    }
+
+    #[test]
+    fn purged_sales_leave_nothing_behind() {
+        init();
+        set_setting(&LOG_LEVEL, LogLevel::Off.to_setting()).unwrap();
+        let timber = add_timber(TimberPayload {
+            timber_type: "pine".to_string(),
+            timber_size: "2x4".to_string(),
+            quantity: 10,
+            default_unit_price: Some(50),
+            ..Default::default()
+        })
+        .unwrap();
+        let item = SalesPayload {
+            timber_id: timber.id,
+            quantity: 1,
+            ..Default::default()
+        };
+        let order = create_order("ann".to_string(), vec![item.clone(), item]).unwrap();
+        let (first, second) = (order.sale_ids[0], order.sale_ids[1]);
+        update_sales(
+            first,
+            SalesUpdatePayload {
+                id: first,
+                quantity: 1,
+                price: 45,
+            },
+        )
+        .unwrap();
+        delete_sales(first).unwrap();
+        advance_time(MIN_PURGE_AGE_NS + DAY_NS);
+        assert_eq!(
+            purge_cancelled_sales_before(time() - MIN_PURGE_AGE_NS),
+            Ok(1)
+        );
+        assert!(PRICE_HISTORY.with(|service| service.borrow().is_empty()));
+        assert!(SALE_ORDERS.with(|index| !index.borrow().contains_key(&first)));
+        assert_eq!(get_order(order.id).unwrap().order.sale_ids, vec![second]);
+
+        delete_sales(second).unwrap();
+        advance_time(MIN_PURGE_AGE_NS + DAY_NS);
+        assert_eq!(
+            purge_cancelled_sales_before(time() - MIN_PURGE_AGE_NS),
+            Ok(1)
+        );
+        assert!(get_order(order.id).is_err());
+    }
+
+    #[test]
+    fn merging_rejects_sales_of_timber_outside_the_snapshot() {
+        init();
+        set_setting(&LOG_LEVEL, LogLevel::Off.to_setting()).unwrap();
+        let sales = Sales {
+            id: 2,
+            timber_id: 1,
+            quantity: 1,
+            price: 10,
+            created_at: time(),
+            ..Default::default()
+        };
+        let snapshot = |sales: Sales| {
+            Encode!(&Snapshot {
+                sales: vec![sales],
+                ..Default::default()
+            })
+            .unwrap()
+        };
+        assert!(import_snapshot(snapshot(sales.clone()), true).is_err());
+        let corrupted = Sales {
+            decode_error: Some("bad bytes".to_string()),
+            ..sales
+        };
+        assert!(import_snapshot(snapshot(corrupted), false).is_err());
+        assert_eq!(sales_count(), 0);
+    }
+
+    #[test]
+    fn the_owner_approves_adjustments_staff_ask_for() {
+        init();
+        set_setting(&LOG_LEVEL, LogLevel::Off.to_setting()).unwrap();
+        let timber = add_timber(TimberPayload {
+            timber_type: "pine".to_string(),
+            timber_size: "2x4".to_string(),
+            quantity: 10,
+            ..Default::default()
+        })
+        .unwrap();
+        let staff = call_as(2);
+        call_as(1);
+        add_authorized_principal(staff).unwrap();
+
+        call_as(2);
+        let adjustment = request_adjustment(timber.id, -3, "broken boards".to_string()).unwrap();
+        assert!(approve_adjustment(adjustment.id).is_err());
+        call_as(1);
+        assert_eq!(approve_adjustment(adjustment.id).unwrap().quantity, 7);
+        assert_stock(timber.id, 7);
+    }
+
+    #[test]
+    fn existence_checks_follow_the_tenant_index() {
+        init();
+        set_setting(&LOG_LEVEL, LogLevel::Off.to_setting()).unwrap();
+        let add = || {
+            add_timber(TimberPayload {
+                timber_type: "pine".to_string(),
+                timber_size: "2x4".to_string(),
+                quantity: 10,
+                default_unit_price: Some(50),
+                ..Default::default()
+            })
+            .unwrap()
+        };
+        let gone = add();
+        let sold = add();
+        let moved = add();
+        let sale = add_sales(SalesPayload {
+            timber_id: sold.id,
+            quantity: 1,
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(timber_exists(gone.id) && sales_exists(sale.id));
+        call_as(2);
+        assert!(!timber_exists(sold.id) && !sales_exists(sale.id));
+
+        call_as(1);
+        delete_timber(gone.id).unwrap();
+        delete_sales(sale.id).unwrap();
+        delete_timber(sold.id).unwrap();
+        assert!(!timber_exists(gone.id) && !sales_exists(sale.id));
+        let last = compact_timber_ids().unwrap();
+        assert_eq!(last, 1);
+        assert!(timber_exists(1) && !timber_exists(moved.id));
+    }
+
+    #[test]
+    fn legacy_timber_is_normalized_once() {
+        let legacy = Timber {
+            timber_type: " Pine".to_string(),
+            timber_size: "2 X 4".to_string(),
+            supplier: Some("Acme ".to_string()),
+            ..Default::default()
+        };
+        let normalized = normalized_timber(&legacy).unwrap();
+        assert_eq!(normalized.timber_type, "pine");
+        assert_eq!(normalized.timber_size, "2x4");
+        assert_eq!(normalized.supplier.as_deref(), Some("Acme"));
+        assert!(normalized_timber(&normalized).is_none());
+    }
+
+    #[test]
+    fn weekdays_count_from_sunday_in_utc() {
+        assert_eq!(utc_weekday(0), 4);
+        //saturday 2 march 2024, 23:59:59 and then sunday 3 march 2024 at midnight utc
+        assert_eq!(utc_weekday(1_709_423_999_000_000_000), 6);
+        assert_eq!(utc_weekday(1_709_424_000_000_000_000), 0);
+    }
+
+    #[test]
+    fn scaled_quantities_round_trip_through_strings() {
+        assert_eq!(format_quantity(125, 2), "1.25");
+        assert_eq!(format_quantity(5, 3), "0.005");
+        assert_eq!(format_quantity(42, 0), "42");
+        assert_eq!(parse_quantity("1.25", 2), Ok(125));
+        assert_eq!(parse_quantity("1.3", 2), Ok(130));
+        assert_eq!(parse_quantity("7", 2), Ok(700));
+        for text in ["", ".5", "1.", "1.255", "-1", "1,5", "1.2.3"] {
+            assert!(parse_quantity(text, 2).is_err(), "{}", text);
+        }
+        assert!(parse_quantity("1.5", 0).is_err());
+    }
+}
 
 //need this to generate candid
 ic_cdk::export_candid!();